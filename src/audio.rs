@@ -0,0 +1,111 @@
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+
+use std::time::{Duration, Instant};
+
+const BEEP_FREQUENCY: f32 = 440.0;
+const BEEP_VOLUME: f32 = 0.15;
+// the collision click is a few milliseconds of a higher tone
+const CLICK_FREQUENCY: f32 = 1760.0;
+const CLICK_MS: u32 = 6;
+
+pub const DEFAULT_MIN_BEEP_MS: u64 = 30;
+
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    // the sound timer tone, always wins over a click
+    tone: bool,
+    click_phase_inc: f32,
+    click_len: u32,
+    // samples of the click still to play
+    click_remaining: u32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            let phase_inc = if self.tone {
+                self.phase_inc
+            } else if self.click_remaining > 0 {
+                self.click_remaining -= 1;
+                self.click_phase_inc
+            } else {
+                *x = 0.0;
+                continue;
+            };
+            *x = if self.phase <= 0.5 { self.volume } else { -self.volume };
+            self.phase = (self.phase + phase_inc) % 1.0;
+        }
+    }
+}
+
+// plays a tone while the sound timer is running.
+// the sound timer counts in 60Hz ticks, so a timer of 1 would only beep for ~16ms
+// which is barely audible, once triggered the beeper stays on for at least min_beep.
+// it can also play one shot clicks, the device only runs while there is something to play
+pub struct Beeper {
+    device: AudioDevice<SquareWave>,
+    min_beep: Duration,
+    // the beeper may not be turned off before this
+    latched_until: Option<Instant>,
+}
+
+impl Beeper {
+    pub fn new(audio: &AudioSubsystem, min_beep: Duration) -> Result<Self, String> {
+        let spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio.open_playback(None, &spec, |spec| SquareWave {
+            phase_inc: BEEP_FREQUENCY / spec.freq as f32,
+            phase: 0.0,
+            volume: BEEP_VOLUME,
+            tone: false,
+            click_phase_inc: CLICK_FREQUENCY / spec.freq as f32,
+            click_len: spec.freq as u32 * CLICK_MS / 1000,
+            click_remaining: 0,
+        })?;
+
+        Ok(Self { device, min_beep, latched_until: None })
+    }
+
+    // call once per frame with the current sound timer
+    pub fn update(&mut self, sound_timer: u8) {
+        let now = Instant::now();
+
+        if sound_timer > 0 {
+            if self.latched_until.is_none() {
+                self.latched_until = Some(now + self.min_beep);
+                self.device.lock().tone = true;
+                self.device.resume();
+            }
+        } else if self.latched_until.is_some_and(|until| now >= until) {
+            self.latched_until = None;
+            self.device.lock().tone = false;
+        }
+
+        // keep the device running until a pending click is finished
+        let idle = {
+            let wave = self.device.lock();
+            !wave.tone && wave.click_remaining == 0
+        };
+        if idle {
+            self.device.pause();
+        }
+    }
+
+    // a short click on top of whatever the sound timer is doing, for --collision-beep
+    pub fn click(&mut self) {
+        {
+            let mut wave = self.device.lock();
+            wave.click_remaining = wave.click_len;
+        }
+        self.device.resume();
+    }
+}
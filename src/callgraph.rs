@@ -0,0 +1,38 @@
+// subroutine calls seen while running, for --call-graph. an edge goes from the
+// subroutine doing the call (the entry the program started at for the top level) to
+// the one called, counted. written as Graphviz DOT: dot -Tsvg pong.dot -o pong.svg
+use crate::LOAD_ADDR;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Clone, Default)]
+pub struct CallGraph {
+    // (caller, callee) -> times called
+    edges: BTreeMap<(u16, u16), u64>,
+    // entries of the subroutines that haven't returned yet, innermost last
+    active: Vec<u16>,
+}
+
+impl CallGraph {
+    pub fn call(&mut self, target: u16) {
+        let caller = self.active.last().copied().unwrap_or(LOAD_ADDR);
+        *self.edges.entry((caller, target)).or_default() += 1;
+        self.active.push(target);
+    }
+
+    pub fn ret(&mut self) {
+        self.active.pop();
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph calls {\n    node [shape=box fontname=monospace];\n");
+        // writing to a String can't fail
+        let _ = writeln!(out, "    \"0x{LOAD_ADDR:03X}\" [label=\"0x{LOAD_ADDR:03X} (start)\"];");
+        for (&(caller, callee), &count) in &self.edges {
+            let _ = writeln!(out, "    \"0x{caller:03X}\" -> \"0x{callee:03X}\" [label=\"{count}\"];");
+        }
+        out += "}\n";
+        out
+    }
+}
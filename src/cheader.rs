@@ -0,0 +1,39 @@
+// the machine state as a C header for --export-header, to embed a known state in
+// another program. one byte per pixel like video_memory, memory only if asked for
+use crate::Chip;
+
+use std::fmt::Write;
+
+fn c_array(out: &mut String, decl: &str, bytes: &[u8]) {
+    let lines: Vec<_> = bytes.chunks(16)
+        .map(|line| line.iter().map(|b| format!("0x{b:02x}")).collect::<Vec<_>>().join(", "))
+        .collect();
+    // writing to a String can't fail
+    let _ = writeln!(out, "{decl}[{}] = {{\n    {}\n}};", bytes.len(), lines.join(",\n    "));
+}
+
+impl Chip {
+    pub fn to_c_header(&self, memory: bool) -> String {
+        let mut out = String::from("#ifndef CHIP8_STATE_H\n#define CHIP8_STATE_H\n\n");
+        let _ = writeln!(out, "#define CHIP8_WIDTH {}", self.screen_width);
+        let _ = writeln!(out, "#define CHIP8_HEIGHT {}", self.screen_height);
+        let _ = writeln!(out, "#define CHIP8_PC 0x{:03x}", self.ip);
+        let _ = writeln!(out, "#define CHIP8_I 0x{:03x}", self.addr_reg);
+        let _ = writeln!(out, "#define CHIP8_DELAY_TIMER {}", self.delay_timer);
+        let _ = writeln!(out, "#define CHIP8_SOUND_TIMER {}", self.sound_timer);
+        // the array can't be empty in C, an empty stack still gets one entry
+        let _ = writeln!(out, "#define CHIP8_STACK_DEPTH {}\n", self.stack.len());
+
+        c_array(&mut out, "static const unsigned char chip8_registers", &self.data_regs);
+        let stack: Vec<_> = self.stack.iter().map(|addr| format!("0x{addr:03x}")).collect();
+        let _ = writeln!(out, "static const unsigned short chip8_stack[{}] = {{ {} }};",
+                         self.stack.len().max(1), if stack.is_empty() { "0".to_string() } else { stack.join(", ") });
+        c_array(&mut out, "static const unsigned char chip8_display", &self.video_memory);
+        if memory {
+            c_array(&mut out, "static const unsigned char chip8_memory", &self.memory[..]);
+        }
+
+        out += "\n#endif\n";
+        out
+    }
+}
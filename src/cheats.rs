@@ -0,0 +1,30 @@
+// poke cheats for --cheats, written into memory at the start of every frame. one
+// `addr = value` per line:
+//
+//     # lives never go down
+//     0x3A0 = 9
+
+use crate::parse_number;
+
+use std::fs;
+
+pub fn load(path: &str) -> Result<Vec<(u16, u8)>, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut cheats = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let cheat = line.split_once('=').and_then(|(addr, value)| {
+            let addr = parse_number(addr.trim()).filter(|&addr| addr <= 0xFFF)?;
+            let value = u8::try_from(parse_number(value.trim())?).ok()?;
+            Some((addr as u16, value))
+        });
+        cheats.push(cheat.ok_or_else(|| format!("line {}: expected `addr = value` with addr in 0-0xFFF and value in 0-255", n + 1))?);
+    }
+
+    Ok(cheats)
+}
@@ -0,0 +1,151 @@
+// the command line options as data. handle_args looks every argument up here to know
+// what it is and how many values follow it, the usage text is generated from the same
+// list. adding an option means an entry here and an arm in handle_args
+pub struct OptionInfo {
+    pub name: &'static str,
+    // other spellings, the first one is shown in the usage text
+    pub aliases: &'static [&'static str],
+    // placeholders for the values that follow, as many as the option takes
+    pub args: &'static [&'static str],
+    // lines after the first one get indented to line up
+    pub help: &'static str,
+    // cargo feature the option needs, it's rejected with a message in builds without it
+    pub feature: Option<&'static str>,
+}
+
+const fn opt(name: &'static str, args: &'static [&'static str], help: &'static str) -> OptionInfo {
+    OptionInfo { name, aliases: &[], args, help, feature: None }
+}
+
+const fn with_feature(feature: &'static str, info: OptionInfo) -> OptionInfo {
+    OptionInfo { feature: Some(feature), ..info }
+}
+
+// in the order the usage text lists them
+pub const OPTIONS: &[OptionInfo] = &[
+    opt("--help", &[], "Show this message"),
+    opt("--list-opcodes", &[], "Print the supported instructions and exit"),
+    OptionInfo {
+        aliases: &["-v", "-vv", "-vvv"],
+        ..opt("--verbose", &[], "Verbose mode, repeat (-vv, -vvv) for more:\ninstructions, registers, memory writes and draws")
+    },
+    opt("--headless", &[], "Run without a window, print a summary on exit"),
+    opt("--max-cycles", &["<n>"], "Stop after n cycles (headless)"),
+    opt("--json", &[], "Print the headless summary as json"),
+    opt("--export-xbm", &["<path>"], "Write the display as an XBM image after a headless run"),
+    opt("--export-header", &["<path>"], "Write the registers and display as a C header after a headless run"),
+    opt("--export-header-memory", &[], "Include memory in the C header"),
+    opt("--splash-xbm", &["<path>"], "Preload the display from an XBM image"),
+    opt("--preview-sprite", &["<addr>", "<n>"], "Print the n byte sprite at addr after a headless run"),
+    opt("--print-display", &[], "Print the display as text after a headless run"),
+    opt("--diff-overlay", &["<path>"], "Highlight the pixels that differ from a --print-display dump"),
+    opt("--crash-dump", &["<dir>"], "Write a crash report there when an exception halts the program"),
+    opt("--save-state", &["<path>"], "Write a save state on exit"),
+    opt("--autosave-interval", &["<secs>"], "Also write a save state every secs while running, alternating\nbetween two files (pong.state.0 and .1, or --save-state with .0/.1)"),
+    opt("--dump-frames", &["<dir>"], "Write every frame as a numbered PPM image there (frame-000000.ppm\nand up), in the window or headless"),
+    opt("--dump-frames-every", &["<n>"], "Only dump every nth frame"),
+    opt("--call-graph", &["<path>"], "Write the subroutine calls made as a Graphviz graph on exit"),
+    opt("--rng-record", &["<path>"], "Write the random numbers the program got on exit"),
+    opt("--rng-replay", &["<path>"], "Hand out the recorded random numbers again, then new ones"),
+    opt("--diff-state", &["<a>", "<b>"], "Print what differs between two save states and exit"),
+    opt("--lint", &[], "Check the program for bad jumps, unknown opcodes and such, then exit"),
+    opt("--disasm-map", &["<path>"], "Write the program as a csv listing (with jump targets) and exit"),
+    opt("--bench", &["<n>"], "Time n cycles of the program (or --demo) a few times, no window"),
+    opt("--batch", &[], "Run every given ROM headless (needs --max-cycles),\nthen report which opcodes were never executed"),
+    opt("--no-config", &[], "Ignore the ROM's settings file (pong.toml or pong.cfg for pong.ch8)"),
+    opt("--hz", &["<n>"], "Target CPU speed in instructions per second (default 1200)"),
+    opt("--max-catchup-cycles", &["<n>"], "Most instructions one frame runs to make up for a slow one\n(default 4 frames worth)"),
+    opt("--authentic-timing", &[], "Run as many instructions per frame as a COSMAC VIP would (ignores --hz)"),
+    opt("--fetch-cost", &["<n>"], "Machine cycles every instruction fetch adds for --authentic-timing (default 0)"),
+    opt("--min-beep-ms", &["<n>"], "Shortest beep in milliseconds (default 30)"),
+    opt("--collision-beep", &[], "Click whenever a sprite draw collides (DXYN sets VF)"),
+    opt("--watch-rom", &[], "Reload and reset when the ROM file changes"),
+    opt("--demo", &[], "Run a small built-in program instead of a ROM file"),
+    opt("--crt", &[], "Draw scanlines and a vignette over the display"),
+    opt("--vip-snow", &[], "Flicker random pixels in frames that draw, like the COSMAC VIP"),
+    opt("--grid", &[], "Draw thin lines between the pixels"),
+    opt("--border", &["<px>"], "Keep a margin of px around the display"),
+    opt("--invert", &[], "Draw the display dark on light"),
+    opt("--texture", &[], "Scale the display via a texture to fill the window exactly"),
+    opt("--dirty-redraw", &[], "Only redraw the pixels that changed (not with --texture)"),
+    opt("--pixel-shape", &["<shape>"], "Draw pixels as square or circle dots (not with --texture)"),
+    opt("--theme", &["<name>"], "Display colors: default, gameboy, amber, ibm or matrix"),
+    opt("--fg", &["<RRGGBB>"], "Color of lit pixels, overrides the theme"),
+    opt("--bg", &["<RRGGBB>"], "Background color, overrides the theme"),
+    opt("--xo-palette", &["<c0,c1,c2,c3>"], "XO-CHIP colors (RRGGBB each) for the background, plane 0,\nplane 1 and both planes, overrides the other colors"),
+    OptionInfo {
+        aliases: &["--keymap-preset"],
+        ..opt("--keypad", &["<layout>"], "Keyboard layout for the hex keypad: default, numpad or two-player\n(left and right half of the keypad on either side of the keyboard)")
+    },
+    opt("--show-keymap", &[], "Print which keyboard key is which keypad key when the window opens"),
+    opt("--fullscreen", &[], "Start in fullscreen mode (F11 toggles)"),
+    opt("--start-paused", &[], "Open the window paused, press P to start"),
+    opt("--run-to-cycle", &["<n>"], "Pause after n instructions"),
+    opt("--frames", &["<n>"], "Close the window after n frames"),
+    opt("--attract", &["<secs>"], "Run the demo after secs without a keypress, any key restarts the ROM"),
+    opt("--play", &["<path>"], "Replay keys recorded with F2 (saved as pong.keys for pong.ch8),\nin the window or headless"),
+    opt("--cheats", &["<path>"], "Write addr = value pokes from the file into memory every frame"),
+    opt("--key-timeout", &["<n>"], "Frames a headless key wait (FX0A) lasts before it gets key 0 (default 60)"),
+    with_feature("json", opt("--dump-json", &["<path>"], "Write the machine state as json after a headless run")),
+    with_feature("json", opt("--dump-json-memory", &[], "Include memory and video memory in the json dump")),
+    with_feature("serve", opt("--serve", &["<port>"], "Run ROMs sent over TCP on localhost and answer with their state")),
+    opt("--set-reg", &["<Vx=n>"], "Set register Vx before running, can be repeated"),
+    opt("--preload", &["<addr:path>"], "Copy a file into memory at addr after loading the ROM, can be repeated"),
+    opt("--set-i", &["<n>"], "Set the address register before running"),
+    opt("--strict-memory", &[], "Warn when reading memory that was never written"),
+    opt("--mem-heatmap", &[], "Show how busy each byte of memory is in the bottom right corner of the window"),
+    opt("--log-writes", &[], "Print every write into the program area (0x200 and up)"),
+    opt("--trace-stack", &[], "Print the call stack on every call and return"),
+    opt("--warn-low-draw", &[], "Warn when a draw reads its sprite from below 0x200 (except font digits)"),
+    opt("--break-on-sound", &[], "Pause whenever FX18 starts the sound"),
+    opt("--max-rom-size", &["<n>"], "Refuse ROMs larger than n bytes (default and most 3584)"),
+    opt("--skip-illegal", &[], "Log and skip illegal instructions instead of halting"),
+    opt("--illegal-tolerance", &["<n>"], "Skip up to n illegal instructions, halt on the next one"),
+    opt("--resolution", &["<WxH>"], "Display size in pixels (default 64x32)"),
+    opt("--quirks", &["<preset>"], "Interpreter quirks: default, chip8, schip, schip1.0 or xochip"),
+    opt("--wrap", &["<axes>"], "Sprites crossing an edge wrap around on these axes (none, x, y\nor xy) and are clipped on the others, overrides the preset"),
+    opt("--vf-per-row", &[], "DXYN sets VF for every row, so only the last one counts, overrides the preset"),
+    opt("--detect-endian", &[], "Warn if the ROM looks like it was saved byte swapped"),
+    opt("--auto-quirks", &[], "Use the recommended quirks and speed if the ROM is a known one,\nthe default preset otherwise"),
+];
+
+// where the descriptions start, longer option names get a line of their own
+const HELP_COLUMN: usize = 24;
+
+pub fn find(arg: &str) -> Option<&'static OptionInfo> {
+    OPTIONS.iter().find(|info| info.name == arg || info.aliases.contains(&arg))
+}
+
+pub fn usage(path: &str) -> String {
+    let mut out = format!("usage: ./{path} [OPTIONS..] [PATH..]\nOptions:\n");
+
+    for info in OPTIONS {
+        let mut left = format!("    {}", info.name);
+        if let Some(alias) = info.aliases.first() {
+            left += &format!(" | {alias}");
+        }
+        for arg in info.args {
+            left += &format!(" {arg}");
+        }
+
+        if left.len() < HELP_COLUMN {
+            out += &format!("{left:HELP_COLUMN$}");
+        } else {
+            out += &format!("{left}\n{:HELP_COLUMN$}", "");
+        }
+        out += &info.help.replace('\n', &format!("\n{:HELP_COLUMN$}", ""));
+        if let Some(feature) = info.feature {
+            out += &format!("\n{:HELP_COLUMN$}(requires the '{feature}' feature)", "");
+        }
+        out.push('\n');
+    }
+
+    out += &format!("\
+Numbers are decimal or 0x prefixed hex, --opt=value works as well as --opt value.
+No ROM at hand? Try --demo.
+Examples:
+    ./{path} pong.ch8 --quirks schip --hz 700
+    ./{path} --demo --crt --theme amber
+    ./{path} pong.ch8 --headless --max-cycles 100000 --print-display");
+    out
+}
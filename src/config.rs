@@ -0,0 +1,100 @@
+// per-ROM settings, read from pong.toml or pong.cfg next to pong.ch8.
+// every line is `option = value` where option is a long command line option without
+// the leading dashes, for example:
+//
+//     # pong wants the superchip behaviour and a bit more speed
+//     quirks = "schip"
+//     hz = 900
+//     crt = true
+//
+// true turns a flag on, false leaves it off, options with several values take them
+// space separated. the lines are turned into command line arguments and parsed before
+// the real ones, so the command line always wins.
+
+use crate::cli;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn find(rom: &str) -> Option<PathBuf> {
+    ["toml", "cfg"].iter()
+        .map(|ext| Path::new(rom).with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+pub fn load(path: &Path) -> Result<Vec<String>, String> {
+    parse(&fs::read_to_string(path).map_err(|e| e.to_string())?)
+}
+
+fn parse(text: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |msg: String| format!("line {}: {msg}", n + 1);
+
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| error("expected `option = value`".to_string()))?;
+        let key = format!("--{}", key.trim().replace('_', "-"));
+        let info = cli::find(&key).ok_or_else(|| error(format!("unknown option '{}'", &key[2..])))?;
+        let value = value.trim();
+
+        // options taking several values get them space separated
+        let values: Vec<&str> = match info.args.len() {
+            1 => vec![value.trim_matches('"')],
+            _ => value.trim_matches('"').split_whitespace().collect(),
+        };
+        match value {
+            "true" | "false" if !info.args.is_empty() => return Err(error(format!("{} needs a value", &key[2..]))),
+            "true" => args.push(key),
+            "false" => {}
+            _ if info.args.is_empty() => return Err(error(format!("{} is a flag, expected true or false", &key[2..]))),
+            _ if values.len() != info.args.len() => {
+                return Err(error(format!("{} takes {} values", &key[2..], info.args.len())));
+            }
+            _ => {
+                args.push(key);
+                args.extend(values.into_iter().map(str::to_string));
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn turns_lines_into_arguments() {
+        let text = "# pong\n\nquirks = \"schip\"\n  hz=900\ncrt = true\nfullscreen = false\nmax_cycles = 10\n";
+        assert_eq!(parse(text), Ok(strings(&["--quirks", "schip", "--hz", "900", "--crt", "--max-cycles", "10"])));
+    }
+
+    #[test]
+    fn values_keep_their_spaces() {
+        assert_eq!(parse("save-state = \"my states/pong.state\""), Ok(strings(&["--save-state", "my states/pong.state"])));
+        assert_eq!(parse("preview-sprite = 0x300 5"), Ok(strings(&["--preview-sprite", "0x300", "5"])));
+    }
+
+    #[test]
+    fn unknown_options_are_refused() {
+        assert_eq!(parse("# x\nspeed = 900"), Err("line 2: unknown option 'speed'".to_string()));
+    }
+
+    #[test]
+    fn bad_values_are_refused() {
+        assert_eq!(parse("hz"), Err("line 1: expected `option = value`".to_string()));
+        assert_eq!(parse("crt = 5"), Err("line 1: crt is a flag, expected true or false".to_string()));
+        assert_eq!(parse("hz = true"), Err("line 1: hz needs a value".to_string()));
+        assert_eq!(parse("preview-sprite = 0x300"), Err("line 1: preview-sprite takes 2 values".to_string()));
+    }
+}
@@ -0,0 +1,90 @@
+// crash reports for --crash-dump, a plain text file with everything needed to make
+// sense of an exception: the exception, registers, stack, the last instructions that
+// ran and what was on screen
+
+use crate::{disasm, Chip, ChipException};
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn report(chip: &Chip, exception: &ChipException) -> String {
+    let mut out = String::new();
+
+    // writing to a String can't fail
+    let _ = writeln!(out, "exception: {exception:?}");
+    let _ = writeln!(out, "ip: 0x{:03X}  I: 0x{:03X}  DT: {}  ST: {}  cycles: {}",
+                     chip.ip, chip.addr_reg, chip.delay_timer, chip.sound_timer, chip.cycles);
+    let regs: Vec<_> = chip.data_regs.iter().enumerate().map(|(i, v)| format!("V{i:X}={v:02X}")).collect();
+    let _ = writeln!(out, "registers: {}", regs.join(" "));
+    let stack: Vec<_> = chip.stack.iter().map(|addr| format!("0x{addr:03X}")).collect();
+    let _ = writeln!(out, "stack: [{}]", stack.join(", "));
+
+    let _ = writeln!(out, "\nlast {} instructions, oldest first:", chip.history.len());
+    for &(addr, instr) in &chip.history {
+        let text = disasm::disassemble(instr).unwrap_or_else(|| format!("??? (DW 0x{instr:04X})"));
+        let _ = writeln!(out, "0x{addr:03X}: {instr:04X}  {text}");
+    }
+
+    let _ = writeln!(out, "\ndisplay:");
+    out += &chip.framebuffer_ascii();
+
+    out
+}
+
+// write the report to dir/crash-<unix time>-<cycles>.txt, creating dir if needed.
+// --batch can crash several ROMs within a second, possibly at the same cycle, so a
+// name that's taken gets a counter as well instead of being overwritten
+pub fn write(dir: &str, chip: &Chip, exception: &ChipException) -> Result<PathBuf, String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    let path = file_name(Path::new(dir), secs, chip.cycles);
+    fs::write(&path, report(chip, exception)).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+fn file_name(dir: &Path, secs: u64, cycles: u64) -> PathBuf {
+    let base = format!("crash-{secs}-{cycles}");
+    let mut path = dir.join(format!("{base}.txt"));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("{base}-{n}.txt"));
+    }
+    path
+}
+
+// for the frontends, which only need to tell the user where it went
+pub fn dump(dir: &str, chip: &Chip, exception: &ChipException) {
+    match write(dir, chip, exception) {
+        Ok(path) => println!("wrote crash report to '{}'", path.display()),
+        Err(e) => eprintln!("Couldn't write a crash report to '{dir}' - {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crashes_in_the_same_second_get_their_own_file() {
+        let dir = std::env::temp_dir().join(format!("chip8-crash-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let chip = Chip::default();
+        let dir_name = dir.to_str().unwrap();
+
+        let first = write(dir_name, &chip, &ChipException::Exit).unwrap();
+        let second = write(dir_name, &chip, &ChipException::Exit).unwrap();
+        assert_ne!(first, second);
+        assert!(first.exists() && second.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn file_name_includes_the_cycles() {
+        let name = file_name(Path::new("no-such-dir"), 1700000000, 1234);
+        assert_eq!(name, Path::new("no-such-dir").join("crash-1700000000-1234.txt"));
+    }
+}
@@ -0,0 +1,79 @@
+// commands typed into the terminal while the window is paused (press Enter in the
+// window to get a prompt). the window doesn't respond until the command is done
+//
+//     p <n>        show the n byte sprite at I
+//     set Vx <n>   set a register
+//     set I <n>    set the address register
+
+use crate::{parse_number, sprite_pixels, Chip};
+
+use std::io::{self, Write};
+
+pub fn prompt(chip: &mut Chip) {
+    print!("> ");
+    let _ = io::stdout().flush();
+
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line).is_ok() {
+        run(chip, line.trim());
+    }
+}
+
+fn run(chip: &mut Chip, line: &str) {
+    let words: Vec<_> = line.split_whitespace().collect();
+
+    match words.as_slice() {
+        [] => {}
+        ["p", n] => match parse_number(n) {
+            Some(n) => print_lines(sprite_preview(chip, chip.addr_reg, n as usize)),
+            None => println!("expected a sprite height, got '{n}'"),
+        },
+        ["set", target, value] => set(chip, target, value),
+        _ => println!("unknown command '{line}'"),
+    }
+}
+
+fn set(chip: &mut Chip, target: &str, value: &str) {
+    let Some(value) = parse_number(value) else {
+        println!("expected a number, got '{value}'");
+        return;
+    };
+
+    if target.eq_ignore_ascii_case("I") {
+        match u16::try_from(value).ok().filter(|&addr| addr <= 0xFFF) {
+            Some(addr) => chip.addr_reg = addr,
+            None => println!("I only holds 0-0xFFF"),
+        }
+        return;
+    }
+
+    let reg = target.strip_prefix(['V', 'v'])
+        .filter(|reg| reg.len() == 1)
+        .and_then(|reg| u8::from_str_radix(reg, 16).ok());
+    match (reg, u8::try_from(value)) {
+        (Some(reg), Ok(value)) => chip.data_regs[reg as usize] = value,
+        (None, _) => println!("unknown register '{target}', expected V0-VF or I"),
+        (_, Err(_)) => println!("registers only hold 0-255"),
+    }
+}
+
+fn print_lines(lines: Vec<String>) {
+    for line in lines {
+        println!("{line}");
+    }
+}
+
+// n rows of the sprite at addr the way DXYN would draw them, '#' for set pixels
+pub fn sprite_preview(chip: &Chip, addr: u16, n: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for row in 0..n {
+        match chip.read_mem(addr.saturating_add(row as u16)) {
+            Ok(byte) => lines.push(sprite_pixels(byte).iter().map(|&set| if set { '#' } else { '.' }).collect()),
+            Err(e) => {
+                lines.push(format!("{e:?}"));
+                break;
+            }
+        }
+    }
+    lines
+}
@@ -0,0 +1,192 @@
+// the instruction set as data, used for disassembly and anything else that needs to
+// know what exec implements. keep this in sync when adding opcodes to exec
+pub struct OpcodeInfo {
+    // hex digits are fixed nibbles, X/Y are registers, N/NN/NNN immediate values
+    pub pattern: &'static str,
+    // {x}, {y}, {n}, {nn} and {nnn} get replaced with the operands
+    pub mnemonic: &'static str,
+    pub category: &'static str,
+    pub description: &'static str,
+}
+
+const fn op(pattern: &'static str, mnemonic: &'static str, category: &'static str, description: &'static str) -> OpcodeInfo {
+    OpcodeInfo { pattern, mnemonic, category, description }
+}
+
+// order matters, the first matching pattern wins (00E0 etc. have to come before 0NNN)
+pub const OPCODES: &[OpcodeInfo] = &[
+    op("00E0", "CLS", "display", "clear the screen"),
+    op("00EE", "RET", "flow", "return from a subroutine"),
+    op("00FD", "EXIT", "flow", "stop the program (SCHIP)"),
+    op("0NNN", "SYS {nnn}", "flow", "call a machine code routine, run like CALL NNN"),
+    op("1NNN", "JP {nnn}", "flow", "jump to NNN"),
+    op("2NNN", "CALL {nnn}", "flow", "call the subroutine at NNN"),
+    op("BNNN", "JP V0, {nnn}", "flow", "jump to NNN + V0 (the jump_vx quirk uses VX)"),
+    op("3XNN", "SE V{x}, {nn}", "conditional", "skip the next instruction if VX == NN"),
+    op("4XNN", "SNE V{x}, {nn}", "conditional", "skip the next instruction if VX != NN"),
+    op("5XY0", "SE V{x}, V{y}", "conditional", "skip the next instruction if VX == VY"),
+    op("9XY0", "SNE V{x}, V{y}", "conditional", "skip the next instruction if VX != VY"),
+    op("6XNN", "LD V{x}, {nn}", "registers", "set VX to NN"),
+    op("7XNN", "ADD V{x}, {nn}", "arithmetic", "add NN to VX, VF is left alone"),
+    op("8XY0", "LD V{x}, V{y}", "registers", "set VX to VY"),
+    op("8XY1", "OR V{x}, V{y}", "bitwise", "set VX to VX | VY"),
+    op("8XY2", "AND V{x}, V{y}", "bitwise", "set VX to VX & VY"),
+    op("8XY3", "XOR V{x}, V{y}", "bitwise", "set VX to VX ^ VY"),
+    op("8XY4", "ADD V{x}, V{y}", "arithmetic", "add VY to VX, VF is the carry"),
+    op("8XY5", "SUB V{x}, V{y}", "arithmetic", "subtract VY from VX, VF is the borrow"),
+    op("8XY6", "SHR V{x}, V{y}", "bitwise", "set VX to VY >> 1, VF is the bit shifted out"),
+    op("8XY7", "SUBN V{x}, V{y}", "arithmetic", "set VX to VY - VX, VF is the borrow"),
+    op("8XYE", "SHL V{x}, V{y}", "bitwise", "set VX to VY << 1, VF is the bit shifted out"),
+    op("CXNN", "RND V{x}, {nn}", "random", "set VX to a random byte masked with NN"),
+    op("ANNN", "LD I, {nnn}", "memory", "set I to NNN"),
+    op("FX1E", "ADD I, V{x}", "memory", "add VX to I"),
+    op("FX29", "LD F, V{x}", "memory", "point I at the font sprite for the digit in VX"),
+    op("FX33", "LD B, V{x}", "memory", "store the decimal digits of VX at I, I+1 and I+2"),
+    op("FX55", "LD [I], V{x}", "memory", "store V0 to VX at I"),
+    op("FX65", "LD V{x}, [I]", "memory", "load V0 to VX from I"),
+    op("DXYN", "DRW V{x}, V{y}, {n}", "display", "draw the N byte sprite at I to (VX, VY), VF is set on collision (SCHIP: N = 0 draws 16x16)"),
+    op("EX9E", "SKP V{x}", "input", "skip the next instruction if the key in VX is pressed"),
+    op("EXA1", "SKNP V{x}", "input", "skip the next instruction if the key in VX is not pressed"),
+    op("FX0A", "LD V{x}, K", "input", "wait for a key press and store the key in VX"),
+    op("FX07", "LD V{x}, DT", "timers", "set VX to the delay timer"),
+    op("FX15", "LD DT, V{x}", "timers", "set the delay timer to VX"),
+    op("FX18", "LD ST, V{x}", "timers", "set the sound timer to VX"),
+];
+
+fn matches(pattern: &str, instr: u16) -> bool {
+    pattern.chars().enumerate().all(|(i, c)| {
+        let nibble = (instr >> (12 - 4 * i)) & 0xF;
+        c.to_digit(16).is_none_or(|digit| digit as u16 == nibble)
+    })
+}
+
+pub fn decode(instr: u16) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|info| matches(info.pattern, instr))
+}
+
+// "LD V3, 0x1A", or None if the instruction isn't implemented
+pub fn disassemble(instr: u16) -> Option<String> {
+    decode(instr).map(|info| {
+        info.mnemonic
+            .replace("{x}", &format!("{:X}", (instr >> 8) & 0xF))
+            .replace("{y}", &format!("{:X}", (instr >> 4) & 0xF))
+            .replace("{nnn}", &format!("0x{:03X}", instr & 0xFFF))
+            .replace("{nn}", &format!("0x{:02X}", instr & 0xFF))
+            .replace("{n}", &format!("{}", instr & 0xF))
+    })
+}
+
+// how many of the 16 bit words in code decode to a known instruction, optionally
+// reading them little endian. only a heuristic, ROMs mix code with sprite data.
+// SYS doesn't count, hardly anything uses it but it matches every word starting with
+// a zero byte, which is what the immediate of a swapped 6XNN or 7XNN usually looks like
+pub fn decodable_words(code: &[u8], swapped: bool) -> usize {
+    code.chunks_exact(2)
+        .map(|word| if swapped {
+            u16::from_le_bytes([word[0], word[1]])
+        } else {
+            u16::from_be_bytes([word[0], word[1]])
+        })
+        .filter(|&instr| decode(instr).is_some_and(|info| info.pattern != "0NNN"))
+        .count()
+}
+
+// disassemble count instructions starting at addr, one line per instruction.
+// anything that doesn't decode is shown as raw data
+pub fn listing(memory: &[u8], addr: u16, count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| addr as usize + 2 * i)
+        .take_while(|&addr| addr + 1 < memory.len())
+        .map(|addr| {
+            let instr = u16::from_be_bytes([memory[addr], memory[addr + 1]]);
+            let text = disassemble(instr).unwrap_or_else(|| format!("??? (DW 0x{instr:04X})"));
+            format!("0x{addr:03X}: {instr:04X}  {text}")
+        })
+        .collect()
+}
+
+// the len bytes at start as csv for --disasm-map, one row per word:
+//
+//     address,bytes,mnemonic,operands,is_jump_target
+//     0x200,00E0,CLS,,false
+//     0x202,A22A,LD,"I, 0x22A",false
+//
+// words that don't decode are DW rows. is_jump_target marks addresses some JP, CALL
+// or SYS in the listing points at, BNNN jumps depend on V0 and aren't followed
+pub fn map(memory: &[u8], start: u16, len: usize) -> Vec<String> {
+    let end = (start as usize + len).min(memory.len());
+    let words: Vec<(usize, u16)> = (start as usize..end)
+        .step_by(2)
+        .take_while(|&addr| addr + 1 < end)
+        .map(|addr| (addr, u16::from_be_bytes([memory[addr], memory[addr + 1]])))
+        .collect();
+
+    let targets: Vec<usize> = words.iter()
+        .filter(|&&(_, instr)| decode(instr).is_some_and(|info| matches!(info.pattern, "1NNN" | "2NNN" | "0NNN")))
+        .map(|&(_, instr)| (instr & 0xFFF) as usize)
+        .collect();
+
+    let mut lines = vec!["address,bytes,mnemonic,operands,is_jump_target".to_string()];
+    for (addr, instr) in words {
+        let text = disassemble(instr).unwrap_or_else(|| format!("DW 0x{instr:04X}"));
+        let (mnemonic, operands) = text.split_once(' ').unwrap_or((&text, ""));
+        let operands = if operands.contains(',') { format!("\"{operands}\"") } else { operands.to_string() };
+        lines.push(format!("0x{addr:03X},{instr:04X},{mnemonic},{operands},{}", targets.contains(&addr)));
+    }
+    lines
+}
+
+// the whole table grouped by category, for --list-opcodes
+pub fn opcode_table() -> Vec<String> {
+    let mut categories: Vec<&str> = Vec::new();
+    for info in OPCODES {
+        if !categories.contains(&info.category) {
+            categories.push(info.category);
+        }
+    }
+
+    let mut lines = Vec::new();
+    for category in categories {
+        lines.push(format!("{category}:"));
+        for info in OPCODES.iter().filter(|info| info.category == category) {
+            let mnemonic = info.mnemonic
+                .replace("{x}", "X")
+                .replace("{y}", "Y")
+                .replace("{nnn}", "NNN")
+                .replace("{nn}", "NN")
+                .replace("{n}", "N");
+            lines.push(format!("    {}  {mnemonic:<16} {}", info.pattern, info.description));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_marks_jump_and_call_targets() {
+        let mut memory = [0; 4096];
+        // CLS, I = 0x20A, call 0x208, jump 0x202, RET, then a word that isn't an instruction
+        let rom = [0x00, 0xE0, 0xA2, 0x0A, 0x22, 0x08, 0x12, 0x02, 0x00, 0xEE, 0xFF, 0xFF];
+        memory[0x200..0x20C].copy_from_slice(&rom);
+        assert_eq!(map(&memory, 0x200, rom.len()), [
+            "address,bytes,mnemonic,operands,is_jump_target",
+            "0x200,00E0,CLS,,false",
+            "0x202,A20A,LD,\"I, 0x20A\",true",
+            "0x204,2208,CALL,0x208,false",
+            "0x206,1202,JP,0x202,false",
+            "0x208,00EE,RET,,true",
+            "0x20A,FFFF,DW,0xFFFF,false",
+        ]);
+    }
+
+    #[test]
+    fn map_stops_at_the_end() {
+        let memory = [0x12; 4096];
+        // an odd length leaves half a word, which isn't listed
+        assert_eq!(map(&memory, 0x200, 3).len(), 2);
+        assert_eq!(map(&memory, 0xFFE, 10), ["address,bytes,mnemonic,operands,is_jump_target", "0xFFE,1212,JP,0x212,false"]);
+    }
+}
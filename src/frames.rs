@@ -0,0 +1,70 @@
+// --dump-frames: the display as a numbered PPM image per frame, for putting a video
+// together outside, e.g. ffmpeg -framerate 60 -i frame-%06d.ppm pong.mp4. only the
+// display in the ROM's colors, none of the window's overlays. every pixel becomes a
+// SCALE sized block so the images don't need scaling with a blurry filter afterwards
+
+use crate::{gfx, Chip, Options};
+
+use std::fs;
+use std::path::PathBuf;
+
+const SCALE: usize = 10;
+
+pub struct FrameDump {
+    dir: PathBuf,
+    // write every nth frame
+    every: u64,
+    frame: u64,
+    // images written, the number of the next one. ffmpeg wants them without gaps
+    written: u64,
+    // a full disk shouldn't print an error 60 times a second
+    failed: bool,
+}
+
+impl FrameDump {
+    pub fn new(options: &Options) -> Option<Self> {
+        let dir = options.dump_frames.as_ref()?;
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("Couldn't create '{dir}' for the frames - {e}");
+            return None;
+        }
+        Some(Self { dir: dir.into(), every: options.dump_frames_every.unwrap_or(1), frame: 0, written: 0, failed: false })
+    }
+
+    // once per frame, after the frame ran
+    pub fn tick(&mut self, chip: &Chip, options: &Options) {
+        let due = self.frame.is_multiple_of(self.every);
+        self.frame += 1;
+        if self.failed || !due {
+            return;
+        }
+        let path = self.dir.join(format!("frame-{:06}.ppm", self.written));
+        match fs::write(&path, ppm(chip, options)) {
+            Ok(()) => self.written += 1,
+            Err(e) => {
+                eprintln!("Couldn't write '{}', not dumping any more frames - {e}", path.display());
+                self.failed = true;
+            }
+        }
+    }
+}
+
+// binary PPM (P6), what about every image tool reads
+fn ppm(chip: &Chip, options: &Options) -> Vec<u8> {
+    let palette = options.palette4();
+    let (width, height) = (chip.screen_width as usize, chip.screen_height as usize);
+    let mut out = format!("P6\n{} {}\n255\n", width * SCALE, height * SCALE).into_bytes();
+
+    for row in chip.video_memory.chunks(width) {
+        let line: Vec<u8> = row.iter()
+            .flat_map(|&pixel| {
+                let (r, g, b) = palette.colors[gfx::color_index(pixel, options.invert)];
+                [r, g, b].repeat(SCALE)
+            })
+            .collect();
+        for _ in 0..SCALE {
+            out.extend_from_slice(&line);
+        }
+    }
+    out
+}
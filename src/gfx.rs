@@ -1,60 +1,580 @@
-use crate::{Chip, SCREEN_WIDTH, SCREEN_HEIGHT, ChipException};
+use crate::{Chip, Options, ChipException, DEMO_ROM, FONT_DATA};
+use crate::audio::Beeper;
+use crate::{crash, debugger, disasm, keyrec, timing};
+use crate::frames::FrameDump;
+use crate::keypad::Keypad;
+use crate::keyrec::{Player, Recorder};
+use crate::savestate::Autosave;
 
 use sdl2::pixels::Color;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::event::Event;
+use sdl2::render::{BlendMode, Canvas, RenderTarget, Texture, TextureCreator};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::video::{FullscreenType, Window, WindowContext};
+use sdl2::event::{Event, WindowEvent};
 use sdl2::EventPump;
 use sdl2::keyboard::Keycode;
 use sdl2::rect::Rect;
 
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use std::thread;
+use std::path::Path;
+use std::fs;
+use std::io::{self, Write};
 
 const WINDOW_WIDTH: u32 = 1024;
 const WINDOW_HEIGHT: u32 = 512;
 
-const CELL_HEIGHT: u32 = WINDOW_HEIGHT / SCREEN_HEIGHT;
-const CELL_WIDTH: u32 = WINDOW_WIDTH / SCREEN_WIDTH;
+// where the chip display goes inside the window. cells are always square and as large
+// as fit into the drawable area, the leftover space is split evenly (letterboxing) so the
+// display stays centered and keeps its 2:1 aspect ratio however the window is resized.
+// drawing through a texture (--texture) lets SDL do the scaling, so the display fills
+// all of the space it can instead of stopping at a whole number of pixels per cell
+#[derive(Clone, Copy)]
+struct Layout {
+    x: i32,
+    y: i32,
+    cell_width: u32,
+    cell_height: u32,
+    // size of the whole display in output pixels
+    width: u32,
+    height: u32,
+    // size of the chip display in pixels
+    cols: u32,
+    rows: u32,
+}
 
-const CYCLES_PER_FRAME: u32 = 20;
+impl Layout {
+    fn new(canvas: &Canvas<Window>, chip: &Chip, options: &Options) -> Self {
+        let (width, height) = canvas.output_size().unwrap_or((WINDOW_WIDTH, WINDOW_HEIGHT));
+        // --border keeps that much of the window free on every side
+        let (width, height) = (width.saturating_sub(2 * options.border), height.saturating_sub(2 * options.border));
+        let (cols, rows) = (chip.screen_width, chip.screen_height);
+        let cell = (width / cols).min(height / rows).max(1);
 
-fn draw_grid(canvas: &mut Canvas<Window>, chip: &Chip) {
-    for row in 0..SCREEN_HEIGHT {
-        for col in 0..SCREEN_WIDTH {
-            let idx = (row * SCREEN_WIDTH + col) as usize;
+        let (display_width, display_height) = if options.texture {
+            let scale = (width as f64 / cols as f64).min(height as f64 / rows as f64);
+            ((cols as f64 * scale) as u32, (rows as f64 * scale) as u32)
+        } else {
+            (cell * cols, cell * rows)
+        };
 
-            // screen cell is active, color white
-            if chip.video_memory[idx] == 1 {
-                let _ = canvas.fill_rect(Rect::new(
-                    (col * CELL_WIDTH) as i32,
-                    (row * CELL_HEIGHT) as i32, 
-                    CELL_WIDTH, CELL_HEIGHT));
+        Self {
+            x: (options.border + width.saturating_sub(display_width) / 2) as i32,
+            y: (options.border + height.saturating_sub(display_height) / 2) as i32,
+            cell_width: cell,
+            cell_height: cell,
+            width: display_width,
+            height: display_height,
+            cols,
+            rows,
+        }
+    }
+
+    // where one chip pixel ends up. with --texture the cells aren't all the same size,
+    // this matches how SDL stretches the texture over the display (the same edges the
+    // grid lines use), without it it's the same as cell_width/cell_height
+    fn cell_rect(&self, col: u32, row: u32) -> Rect {
+        let left = col * self.width / self.cols;
+        let top = row * self.height / self.rows;
+        let right = (col + 1) * self.width / self.cols;
+        let bottom = (row + 1) * self.height / self.rows;
+        Rect::new(self.x + left as i32, self.y + top as i32, (right - left).max(1), (bottom - top).max(1))
+    }
+}
+
+// never run more than this many frames worth of cycles at once,
+// otherwise a slow frame makes the next one slower and so on.
+// --max-catchup-cycles overrides it
+const MAX_CATCHUP_FRAMES: u32 = 4;
+
+// decides how many cycles to run each frame based on the real time that passed
+struct Pacer {
+    cpu_hz: u32,
+    // most cycles a single frame may run
+    cap: u32,
+    // real time not yet paid for with cycles, in seconds
+    accumulator: f64,
+    last_frame: Instant,
+}
+
+impl Pacer {
+    fn new(options: &Options) -> Self {
+        let cpu_hz = options.cpu_hz();
+        let cap = options.max_catchup_cycles.unwrap_or((cpu_hz / 60).max(1) * MAX_CATCHUP_FRAMES).max(1);
+        Self { cpu_hz, cap, accumulator: 0.0, last_frame: Instant::now() }
+    }
+
+    fn cycles_this_frame(&mut self) -> u32 {
+        let now = Instant::now();
+        self.accumulator += now.duration_since(self.last_frame).as_secs_f64();
+        self.last_frame = now;
+
+        let cycles = (self.accumulator * self.cpu_hz as f64) as u32;
+
+        if cycles > self.cap {
+            // too far behind, drop the backlog instead of trying to catch up
+            self.accumulator = 0.0;
+            self.cap
+        } else {
+            self.accumulator -= cycles as f64 / self.cpu_hz as f64;
+            cycles
+        }
+    }
+}
+
+// how much the crt effect darkens every other line of output pixels
+const SCANLINE_ALPHA: u8 = 70;
+// alpha of the glow drawn around lit cells in crt mode
+const BLEED_ALPHA: u8 = 40;
+// width of the darkened border in crt mode
+const VIGNETTE_STEPS: u32 = 24;
+
+// cells that differ from the --diff-overlay reference
+const DIFF_COLOR: Color = Color::RGB(255, 0, 255);
+
+// cells --vip-snow lights up in a frame with a draw
+const SNOW_PIXELS: u32 = 12;
+
+// size of one font pixel of the HUD in output pixels
+const HUD_SCALE: u32 = 3;
+// how many of the topmost stack entries the HUD shows
+const HUD_STACK_ENTRIES: usize = 3;
+
+fn draw_grid(canvas: &mut Canvas<Window>, chip: &Chip, options: &Options, layout: Layout, hud: bool,
+             texture: Option<&mut Texture>, cache: Option<&mut CellCache>) {
+    let fg = canvas.draw_color();
+
+    if let Some(texture) = texture {
+        draw_texture(canvas, chip, options, layout, texture);
+    } else if let Some(cache) = cache {
+        cache.draw(canvas, chip, options, layout);
+    } else {
+        draw_cells(canvas, chip, options, layout);
+    }
+
+    if options.grid {
+        draw_grid_lines(canvas, chip, options, layout);
+        canvas.set_draw_color(fg);
+    }
+
+    if let Some((width, reference)) = &options.diff_overlay {
+        draw_diff(canvas, chip, layout, *width, reference);
+        canvas.set_draw_color(fg);
+    }
+
+    if options.crt {
+        canvas.set_blend_mode(BlendMode::Blend);
+        draw_crt_overlay(canvas, layout);
+        canvas.set_blend_mode(BlendMode::None);
+        canvas.set_draw_color(fg);
+    }
+
+    if hud {
+        draw_hud(canvas, chip, layout);
+    }
+}
+
+// one pixel per cell into a texture the size of the chip display, which SDL then
+// stretches over the layout with nearest neighbour scaling
+fn draw_texture(canvas: &mut Canvas<Window>, chip: &Chip, options: &Options, layout: Layout, texture: &mut Texture) {
+    let palette = options.palette4();
+    let width = chip.screen_width as usize;
+
+    let _ = texture.with_lock(None, |buf, pitch| {
+        for (row, pixels) in chip.video_memory.chunks(width).enumerate() {
+            for (col, &pixel) in pixels.iter().enumerate() {
+                let (r, g, b) = palette.colors[color_index(pixel, options.invert)];
+                let offset = row * pitch + col * 3;
+                buf[offset..offset + 3].copy_from_slice(&[r, g, b]);
+            }
+        }
+    });
+
+    let _ = canvas.copy(texture, None, Rect::new(layout.x, layout.y, layout.width, layout.height));
+}
+
+// the display drawn cell by cell into a texture that is kept between frames, for
+// --dirty-redraw. only the cells that changed since the last frame get drawn again,
+// the window then gets the whole texture copied over. has to be made again whenever
+// the layout changes. no crt glow, it would leak across the cell edges
+struct CellCache<'a> {
+    texture: Texture<'a>,
+    // what the texture shows, None until the first frame
+    prev: Option<Box<[u8]>>,
+}
+
+impl<'a> CellCache<'a> {
+    fn new(texture_creator: &'a TextureCreator<WindowContext>, layout: Layout) -> Self {
+        let texture = texture_creator
+            .create_texture_target(PixelFormatEnum::RGB24, layout.width.max(1), layout.height.max(1))
+            .unwrap();
+        Self { texture, prev: None }
+    }
+
+    fn draw(&mut self, canvas: &mut Canvas<Window>, chip: &Chip, options: &Options, layout: Layout) {
+        let palette = options.palette4();
+        // a resolution change means everything is different anyway
+        let prev = self.prev.take().filter(|prev| prev.len() == chip.video_memory.len());
+
+        let _ = canvas.with_texture_canvas(&mut self.texture, |target| {
+            for (idx, &pixel) in chip.video_memory.iter().enumerate() {
+                if prev.as_ref().is_some_and(|prev| prev[idx] == pixel) {
+                    continue;
+                }
+                let (col, row) = (idx as u32 % chip.screen_width, idx as u32 / chip.screen_width);
+                let cell = Rect::new(
+                    (col * layout.cell_width) as i32, (row * layout.cell_height) as i32,
+                    layout.cell_width, layout.cell_height);
+                // the old pixel has to go first when only a dot gets drawn over it
+                let color = color_index(pixel, options.invert);
+                if options.round_pixels {
+                    target.set_draw_color(Color::from(palette.colors[0]));
+                    let _ = target.fill_rect(cell);
+                }
+                if color != 0 || !options.round_pixels {
+                    target.set_draw_color(Color::from(palette.colors[color]));
+                    fill_pixel(target, cell, options.round_pixels);
+                }
+            }
+        });
+        self.prev = Some(chip.video_memory.clone());
+
+        let _ = canvas.copy(&self.texture, None, Rect::new(layout.x, layout.y, layout.width, layout.height));
+    }
+}
+
+// one lit pixel, the whole cell or the largest dot that fits it for --pixel-shape circle.
+// cells under 3 output pixels are too small to round off and stay square
+fn fill_pixel<T: RenderTarget>(canvas: &mut Canvas<T>, cell: Rect, round: bool) {
+    if !round || cell.width() < 3 || cell.height() < 3 {
+        let _ = canvas.fill_rect(cell);
+        return;
+    }
+
+    // one line per output row, as wide as the ellipse is at its middle
+    let (rx, ry) = (cell.width() as f32 / 2.0, cell.height() as f32 / 2.0);
+    for y in 0..cell.height() {
+        let dy = (y as f32 + 0.5 - ry) / ry;
+        let half = rx * (1.0 - dy * dy).max(0.0).sqrt();
+        let width = (2.0 * half).round() as u32;
+        if width > 0 {
+            let x = cell.x() + (rx - width as f32 / 2.0).round() as i32;
+            let _ = canvas.fill_rect(Rect::new(x, cell.y() + y as i32, width, 1));
+        }
+    }
+}
+
+// which of the four palette colors a pixel gets. the bits are the planes, --invert
+// flips plane 0 so a single plane display swaps foreground and background
+pub fn color_index(pixel: u8, invert: bool) -> usize {
+    ((pixel ^ invert as u8) & 3) as usize
+}
+
+fn draw_cells(canvas: &mut Canvas<Window>, chip: &Chip, options: &Options, layout: Layout) {
+    let fg = canvas.draw_color();
+    let palette = options.palette4();
+
+    if options.crt {
+        canvas.set_blend_mode(BlendMode::Blend);
+    }
+
+    for row in 0..chip.screen_height {
+        for col in 0..chip.screen_width {
+            let idx = (row * chip.screen_width + col) as usize;
+
+            // the background is already there from clearing the canvas
+            let color = color_index(chip.video_memory[idx], options.invert);
+            if color != 0 {
+                let color = Color::from(palette.colors[color]);
+                canvas.set_draw_color(color);
+                let cell = Rect::new(
+                    layout.x + (col * layout.cell_width) as i32,
+                    layout.y + (row * layout.cell_height) as i32, 
+                    layout.cell_width, layout.cell_height);
+
+                // let the light leak a little into the neighbouring cells
+                if options.crt {
+                    canvas.set_draw_color(Color::RGBA(color.r, color.g, color.b, BLEED_ALPHA));
+                    let _ = canvas.fill_rect(Rect::new(cell.x() - 2, cell.y() - 1, cell.width() + 4, cell.height() + 2));
+                    canvas.set_draw_color(color);
+                }
+
+                fill_pixel(canvas, cell, options.round_pixels);
             } 
         }
     }
+
+    if options.crt {
+        canvas.set_blend_mode(BlendMode::None);
+    }
+    canvas.set_draw_color(fg);
 }
 
-fn freeze(mut events: EventPump) -> ! {
-    loop {
-        match events.wait_event() {
-            Event::Quit { .. } |
-            Event::KeyDown { keycode: Some(Keycode::Q), .. } => {
-                std::process::exit(0);
+// the font only has hex digits, so there are no labels. from the top: the last
+// executed opcode, ip, then the stack starting with the most recent return address
+fn draw_hud(canvas: &mut Canvas<Window>, chip: &Chip, layout: Layout) {
+    let fg = canvas.draw_color();
+
+    let mut lines = vec![chip.last_opcode, chip.ip];
+    lines.extend(chip.stack.iter().rev().take(HUD_STACK_ENTRIES));
+
+    // glyphs are 4x5 with a 1 pixel gap, every line has 4 of them
+    let line_width = (4 * 5 - 1) * HUD_SCALE;
+    let line_height = 6 * HUD_SCALE;
+    let (x, y) = (layout.x + HUD_SCALE as i32, layout.y + HUD_SCALE as i32);
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    let _ = canvas.fill_rect(Rect::new(x - HUD_SCALE as i32, y - HUD_SCALE as i32,
+        line_width + 2 * HUD_SCALE, line_height * lines.len() as u32 + HUD_SCALE));
+
+    canvas.set_draw_color(Color::RGB(255, 200, 0));
+    for (line, value) in lines.iter().enumerate() {
+        for digit in 0..4 {
+            let glyph = ((value >> (12 - 4 * digit)) & 0xF) as u8;
+            draw_glyph(canvas, glyph, x + (digit * 5 * HUD_SCALE) as i32, y + (line as u32 * line_height) as i32);
+        }
+    }
+
+    canvas.set_draw_color(fg);
+}
+
+// bottom left corner: instructions executed so far, then whole seconds since the
+// window opened, both in decimal. toggled with I
+fn draw_counter(canvas: &mut Canvas<Window>, cycles: u64, secs: u64, layout: Layout) {
+    let fg = canvas.draw_color();
+
+    let lines = [cycles.to_string(), secs.to_string()];
+    let digits = lines.iter().map(|line| line.len()).max().unwrap_or(0) as u32;
+    let line_height = 6 * HUD_SCALE;
+    let x = layout.x + HUD_SCALE as i32;
+    let y = layout.y + layout.height as i32 - (lines.len() as u32 * line_height + HUD_SCALE) as i32;
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    let _ = canvas.fill_rect(Rect::new(x - HUD_SCALE as i32, y - HUD_SCALE as i32,
+        (digits * 5 + 1) * HUD_SCALE, line_height * lines.len() as u32 + HUD_SCALE));
+
+    canvas.set_draw_color(Color::RGB(255, 200, 0));
+    for (line, text) in lines.iter().enumerate() {
+        for (digit, c) in text.chars().enumerate() {
+            // only ever '0'..='9' here
+            let glyph = c.to_digit(10).unwrap_or(0) as u8;
+            draw_glyph(canvas, glyph, x + (digit as u32 * 5 * HUD_SCALE) as i32, y + (line as u32 * line_height) as i32);
+        }
+    }
+
+    canvas.set_draw_color(fg);
+}
+
+// the chip keys the way they sit on the VIP keypad
+const KEYPAD_ROWS: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// --vip-snow: a few random cells lit for one frame whenever the program drew, like the
+// VIP's display showed when the CPU got at memory while the video chip was reading it.
+// every draw counts, nothing here waits for vblank. video_memory isn't touched and
+// the CXNN random numbers aren't used up
+fn draw_snow(canvas: &mut Canvas<Window>, chip: &Chip, options: &Options, layout: Layout) {
+    let fg = canvas.draw_color();
+    canvas.set_draw_color(Color::from(options.palette4().colors[1]));
+
+    for _ in 0..SNOW_PIXELS {
+        let col = rand::random::<u32>() % chip.screen_width;
+        let row = rand::random::<u32>() % chip.screen_height;
+        let _ = canvas.fill_rect(layout.cell_rect(col, row));
+    }
+
+    canvas.set_draw_color(fg);
+}
+
+// output pixels per byte of the memory heatmap, a 64x64 square of the 4096 bytes
+const HEATMAP_SCALE: u32 = 2;
+
+// --mem-heatmap in the bottom right corner (the counter has the left one), one row
+// per 64 bytes. untouched bytes are dark, busy ones go through red to yellow
+fn draw_heatmap(canvas: &mut Canvas<Window>, chip: &Chip, layout: Layout) {
+    let Some(heat) = &chip.heat else { return };
+    let fg = canvas.draw_color();
+    let side = 64 * HEATMAP_SCALE;
+    let x = layout.x + layout.width.saturating_sub(side) as i32;
+    let y = layout.y + layout.height.saturating_sub(side) as i32;
+
+    for (addr, cell) in heat.iter().enumerate() {
+        let level = cell.get().min(511);
+        let (red, green) = (level.min(255) as u8, level.saturating_sub(256) as u8);
+        canvas.set_draw_color(Color::RGB(red.max(24), green.max(24), 24));
+        let (col, row) = (addr as u32 % 64, addr as u32 / 64);
+        let _ = canvas.fill_rect(Rect::new(
+            x + (col * HEATMAP_SCALE) as i32, y + (row * HEATMAP_SCALE) as i32,
+            HEATMAP_SCALE, HEATMAP_SCALE));
+    }
+
+    canvas.set_draw_color(fg);
+}
+
+// the keypad in the top right corner of the display, held keys lit. toggled with K
+fn draw_keypad(canvas: &mut Canvas<Window>, keypad: &Keypad, layout: Layout) {
+    let fg = canvas.draw_color();
+
+    // a glyph with a font pixel of room on every side
+    let (key_width, key_height) = (6 * HUD_SCALE, 7 * HUD_SCALE);
+    let x = layout.x + layout.width.saturating_sub(4 * key_width) as i32;
+    let y = layout.y;
+
+    for (row, keys) in KEYPAD_ROWS.iter().enumerate() {
+        for (col, &key) in keys.iter().enumerate() {
+            let (key_x, key_y) = (x + (col as u32 * key_width) as i32, y + (row as u32 * key_height) as i32);
+            let held = keypad.is_pressed(key);
+
+            canvas.set_draw_color(if held { Color::RGB(255, 200, 0) } else { Color::RGB(0, 0, 0) });
+            let _ = canvas.fill_rect(Rect::new(key_x, key_y, key_width, key_height));
+            canvas.set_draw_color(if held { Color::RGB(0, 0, 0) } else { Color::RGB(90, 90, 90) });
+            draw_glyph(canvas, key, key_x + HUD_SCALE as i32, key_y + HUD_SCALE as i32);
+        }
+    }
+
+    canvas.set_draw_color(fg);
+}
+
+// one hex digit from the chip font in the current draw color, HUD_SCALE output pixels per font pixel
+fn draw_glyph(canvas: &mut Canvas<Window>, glyph: u8, x: i32, y: i32) {
+    let glyph = glyph as usize;
+    for (row, bits) in FONT_DATA[glyph * 5..glyph * 5 + 5].iter().enumerate() {
+        for col in 0..4 {
+            if bits & (0x80 >> col) != 0 {
+                let _ = canvas.fill_rect(Rect::new(
+                    x + (col * HUD_SCALE) as i32,
+                    y + (row as u32 * HUD_SCALE) as i32,
+                    HUD_SCALE, HUD_SCALE));
             }
-            _ => {}
         }
     }
 }
 
-fn pause(events: &mut EventPump) {
+// 1px lines along the cell edges in a darker background color, drawn over the cells
+// so nothing moves. with --texture the cells aren't whole pixels, the lines get rounded
+fn draw_grid_lines(canvas: &mut Canvas<Window>, chip: &Chip, options: &Options, layout: Layout) {
+    let (r, g, b) = options.palette4().colors[0];
+    canvas.set_draw_color(Color::RGB(r / 2, g / 2, b / 2));
+
+    for col in 1..chip.screen_width {
+        let x = layout.x + (col * layout.width / chip.screen_width) as i32;
+        let _ = canvas.fill_rect(Rect::new(x, layout.y, 1, layout.height));
+    }
+    for row in 1..chip.screen_height {
+        let y = layout.y + (row * layout.height / chip.screen_height) as i32;
+        let _ = canvas.fill_rect(Rect::new(layout.x, y, layout.width, 1));
+    }
+}
+
+// nothing to compare while the resolution doesn't match the reference
+fn draw_diff(canvas: &mut Canvas<Window>, chip: &Chip, layout: Layout, width: u32, reference: &[u8]) {
+    if width != chip.screen_width || reference.len() != chip.video_memory.len() {
+        return;
+    }
+
+    canvas.set_draw_color(DIFF_COLOR);
+    for (idx, _) in chip.video_memory.iter().zip(reference).enumerate().filter(|(_, (live, want))| live != want) {
+        let (col, row) = (idx as u32 % width, idx as u32 / width);
+        let _ = canvas.fill_rect(layout.cell_rect(col, row));
+    }
+}
+
+// scanlines over every other row of output pixels plus a vignette that darkens the edges,
+// only touches what's presented, video_memory stays as it is
+fn draw_crt_overlay(canvas: &mut Canvas<Window>, layout: Layout) {
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, SCANLINE_ALPHA));
+    for y in (0..layout.height).step_by(2) {
+        let _ = canvas.fill_rect(Rect::new(layout.x, layout.y + y as i32, layout.width, 1));
+    }
+
+    // each step is a 1px frame, the outermost ones end up the darkest
+    for step in 0..VIGNETTE_STEPS {
+        let alpha = (VIGNETTE_STEPS - step) * 4;
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, alpha as u8));
+        let _ = canvas.draw_rect(Rect::new(
+            layout.x + step as i32, layout.y + step as i32,
+            layout.width.saturating_sub(2 * step), layout.height.saturating_sub(2 * step)));
+    }
+}
+
+// switch between windowed and fullscreen desktop mode
+fn toggle_fullscreen(canvas: &mut Canvas<Window>) {
+    let window = canvas.window_mut();
+    let mode = match window.fullscreen_state() {
+        FullscreenType::Off => FullscreenType::Desktop,
+        _ => FullscreenType::Off,
+    };
+
+    if let Err(e) = window.set_fullscreen(mode) {
+        eprintln!("Couldn't toggle fullscreen - {e}");
+    }
+}
+
+// notices when the ROM file gets rewritten, for --watch-rom
+struct RomWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl RomWatcher {
+    fn new(path: &str) -> Self {
+        Self { path: path.to_string(), modified: Self::mtime(path) }
+    }
+
+    fn mtime(path: &str) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    // the file might be missing for a moment while it's being rebuilt,
+    // in that case just keep the last known time and check again later
+    fn changed(&self) -> bool {
+        let modified = Self::mtime(&self.path);
+        modified.is_some() && modified != self.modified
+    }
+
+    // reset the machine and load the current contents of the file. on failure the old
+    // program keeps running, the time is noted either way so a broken file is only
+    // complained about once and tried again when it changes next
+    fn reload(&mut self, chip: &mut Chip) {
+        self.modified = Self::mtime(&self.path);
+        let mut fresh = chip.fresh();
+        match fresh.load_program(&self.path) {
+            Ok(_) => {
+                *chip = fresh;
+                println!("reloaded ROM '{}'", self.path);
+            }
+            Err(e) => {
+                eprintln!("Couldn't reload '{}' - {e}", self.path);
+            }
+        }
+    }
+}
+
+// "Chip-8 Emulator - pong.ch8 [PAUSED]"
+fn set_title(canvas: &mut Canvas<Window>, options: &Options, state: Option<&str>) {
+    let mut title = String::from("Chip-8 Emulator");
+
+    if let Some(name) = Path::new(&options.rom_path).file_name() {
+        title = format!("{title} - {}", name.to_string_lossy());
+    }
+    if let Some(state) = state {
+        title = format!("{title} [{state}]");
+    }
+
+    // only fails if the title contains a nul byte
+    let _ = canvas.window_mut().set_title(&title);
+}
+
+// keep the halted screen up until the user quits
+fn freeze(events: &mut EventPump) {
     loop {
         match events.wait_event() {
             Event::Quit { .. } |
-            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                std::process::exit(0);
-            }
-            Event::KeyDown { keycode: Some(Keycode::P), .. } => {
+            Event::KeyDown { keycode: Some(Keycode::Q), .. } => {
                 return
             }
             _ => {}
@@ -62,18 +582,40 @@ fn pause(events: &mut EventPump) {
     }
 }
 
-fn wait_for_key(chip: &mut Chip, register: u8, events: &mut EventPump) {
+// how much faster the cpu runs while tab is held, timers keep their 60Hz
+const FAST_FORWARD_FACTOR: u32 = 8;
+
+// how many instructions D prints while paused
+const LOOKAHEAD_INSTRUCTIONS: usize = 10;
+
+// how pause() was left
+enum Resume {
+    Run,
+    // run a single frame, then pause again
+    StepFrame,
+    Quit,
+}
+
+fn pause(events: &mut EventPump, chip: &mut Chip) -> Resume {
     loop {
         match events.wait_event() {
             Event::Quit { .. } |
             Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                std::process::exit(0);
+                return Resume::Quit
+            }
+            Event::KeyDown { keycode: Some(Keycode::P), .. } => {
+                return Resume::Run
+            }
+            Event::KeyDown { keycode: Some(Keycode::N), .. } => {
+                return Resume::StepFrame
+            }
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } => {
+                debugger::prompt(chip);
             }
-            Event::KeyDown { keycode, .. }  => {
-                if let Some(key) = keycode.and_then(|key| u8::from_str_radix(&key.to_string(), 16).ok()) {
-                    chip.data_regs[register as usize] = key;
-                    chip.ip += 2;
-                    return;
+            // show what's coming up without executing it
+            Event::KeyDown { keycode: Some(Keycode::D), .. } => {
+                for line in disasm::listing(&chip.memory[..], chip.ip, LOOKAHEAD_INSTRUCTIONS) {
+                    println!("{line}");
                 }
             }
             _ => {}
@@ -81,30 +623,166 @@ fn wait_for_key(chip: &mut Chip, register: u8, events: &mut EventPump) {
     }
 }
 
-const KEY_MAP: [&str; 16] = [
+// the key for each chip key, indexed by the chip key. these are physical layouts,
+// rearranged for the hex keypad of the VIP:
+//
+//     1 2 3 C
+//     4 5 6 D
+//     7 8 9 E
+//     A 0 B F
+pub const KEY_MAP: [&str; 16] = [
     "X", "1", "2", "3",
     "Q", "W", "E", "A",
     "S", "D", "Z", "C",
     "4", "R", "F", "V",
 ];
+
+// the digits sit where they are on the numpad, the letters take the keys that are left:
+//
+//     7 8 9     /         1 2 3 C
+//     4 5 6     *   ->    4 5 6 D
+//     1 2 3     -         7 8 9 E
+//     0 . Enter +         A 0 B F
+pub const NUMPAD_KEY_MAP: [&str; 16] = [
+    "Keypad .", "Keypad 7", "Keypad 8", "Keypad 9",
+    "Keypad 4", "Keypad 5", "Keypad 6", "Keypad 1",
+    "Keypad 2", "Keypad 3", "Keypad 0", "Keypad Enter",
+    "Keypad /", "Keypad *", "Keypad -", "Keypad +",
+];
+
+// for two player homebrew, each player gets half of the keypad on their own side of
+// the keyboard. the left two columns go to player one, the right two to player two:
+//
+//     1 2    - =          1 2 3 C
+//     Q W    [ ]    ->    4 5 6 D
+//     A S    ; '          7 8 9 E
+//     Z X    . /          A 0 B F
+//
+// pong's paddles (1/4 and C/D) end up on 1/Q and =/]
+pub const TWO_PLAYER_KEY_MAP: [&str; 16] = [
+    "X", "1", "2", "-",
+    "Q", "W", "[", "A",
+    "S", ";", "Z", ".",
+    "=", "]", "'", "/",
+];
+
+pub const KEYPADS: [(&str, &[&str; 16]); 3] = [
+    ("default", &KEY_MAP),
+    ("numpad", &NUMPAD_KEY_MAP),
+    ("two-player", &TWO_PLAYER_KEY_MAP),
+];
+
+// keys the window handles itself before the keypad gets to see them, a layout can't use
+// them. N only counts while paused, where it steps a frame
+pub const FRONTEND_KEYS: [&str; 10] = ["P", "H", "K", "I", "N", "Tab", "Backspace", "F2", "F11", "Escape"];
+
+// the keys of a layout that the window would take for itself
+pub fn frontend_key_conflicts(key_map: &[&str; 16]) -> Vec<&'static str> {
+    FRONTEND_KEYS.iter().filter(|key| key_map.contains(key)).copied().collect()
+}
+
+pub fn keypad(name: &str) -> Option<&'static [&'static str; 16]> {
+    KEYPADS.iter()
+        .find(|(keypad, _)| *keypad == name)
+        .map(|(_, keys)| *keys)
+}
+
+// --show-keymap: the keypad as it sits on the VIP with the physical key for each chip key
+pub fn keymap_diagram(key_map: &[&str; 16]) -> String {
+    let mut out = String::new();
+    for keys in KEYPAD_ROWS {
+        let cells: Vec<_> = keys.iter().map(|&key| format!("{key:X}: {:<12}", key_map[key as usize])).collect();
+        out += cells.join(" ").trim_end();
+        out.push('\n');
+    }
+    out
+}
+
+fn key_index(key_map: &[&str; 16], key: Keycode) -> Option<usize> {
+    let key = key.to_string();
+    key_map.iter().position(|x| key.eq(x))
+}
  
-pub fn spawn_window(mut chip: Chip) {
+pub fn spawn_window(mut chip: Chip, options: &Options) {
+    if options.show_keymap {
+        print!("{}", keymap_diagram(options.key_map()));
+    }
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+
+    // no sound is better than no emulator
+    let mut beeper = sdl_context.audio()
+        .and_then(|audio| Beeper::new(&audio, options.min_beep()))
+        .map_err(|e| eprintln!("Couldn't open audio device, running without sound - {e}"))
+        .ok();
  
     let window = video_subsystem.window("Chip-8 Emulator", WINDOW_WIDTH, WINDOW_HEIGHT)
         .position_centered()
+        .resizable()
         .build()
         .unwrap();
 
-    let mut key_matrix: [bool; 16] = [false; 16];
+    let palette = options.palette4();
+    let (fg, bg) = (Color::from(palette.colors[1]), Color::from(palette.colors[0]));
+
+    let mut keypad = Keypad::default();
+    let key_map = options.key_map();
+    let mut fast_forward = false;
+    let mut hud = false;
+    let mut show_keypad = false;
+    let mut show_counter = false;
+    let started = Instant::now();
  
     let mut canvas = window.into_canvas().build().unwrap();
+    set_title(&mut canvas, options, None);
+    if options.fullscreen {
+        toggle_fullscreen(&mut canvas);
+    }
+    let mut layout = Layout::new(&canvas, &chip, options);
+    let texture_creator = canvas.texture_creator();
+    let mut display = options.texture.then(|| {
+        // nearest neighbour, anything else blurs the pixels
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "0");
+        texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, chip.screen_width, chip.screen_height).unwrap()
+    });
+    let mut cache = (options.dirty_redraw && !options.texture).then(|| CellCache::new(&texture_creator, layout));
     let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut pacer = Pacer::new(options);
+    let mut watcher = options.watch_rom.then(|| RomWatcher::new(&options.rom_path));
+    let mut autosave = Autosave::new(options);
+    let mut frame_dump = FrameDump::new(options);
+    // the first frame pauses before running anything, show the (empty or splash) display meanwhile
+    let mut p = options.start_paused;
+    // only stops once, resuming carries on normally
+    let mut run_to_cycle = options.run_to_cycle;
+    // frames run so far, pauses don't count. recordings and --play are timed by it
+    let mut frame: u64 = 0;
+    let mut recorder: Option<Recorder> = None;
+    let mut player = options.play.as_ref().map(|events| Player::new(events.clone()));
+    // --attract: the machine as it was at startup, to go back to once the demo is interrupted
+    let startup = options.attract.is_some().then(|| chip.clone());
+    let mut attract = false;
+    let mut last_input = Instant::now();
+    if p {
+        canvas.set_draw_color(bg);
+        canvas.clear();
+        canvas.set_draw_color(fg);
+        draw_grid(&mut canvas, &chip, options, layout, false, display.as_mut(), cache.as_mut());
+        canvas.present();
+    }
     'running: loop {
-        let mut p = false;
-
         for event in event_pump.poll_iter() {
+            if let Event::KeyDown { .. } = event {
+                last_input = Instant::now();
+                // the key only ends the demo, the program doesn't get to see it
+                if let (true, Some(startup)) = (attract, &startup) {
+                    println!("attract: key pressed, restarting the program");
+                    attract = false;
+                    chip = startup.clone();
+                    continue;
+                }
+            }
             match event {
                 Event::Quit {..} |
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
@@ -113,11 +791,51 @@ pub fn spawn_window(mut chip: Chip) {
                 Event::KeyDown { keycode: Some(Keycode::P), .. } => {
                     p = true; 
                 },
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(key) = keycode.map(|key| key.to_string()) {
-                        println!("press: {key}");
-                        if let Some(idx) = KEY_MAP.iter().position(|x| key.eq(x)) {
-                            key_matrix[idx] = true;
+                Event::KeyDown { keycode: Some(Keycode::Tab), .. } => {
+                    fast_forward = true;
+                },
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } => {
+                    fast_forward = false;
+                },
+                Event::KeyDown { keycode: Some(Keycode::H), .. } => {
+                    hud = !hud;
+                },
+                Event::KeyDown { keycode: Some(Keycode::K), .. } => {
+                    show_keypad = !show_keypad;
+                },
+                Event::KeyDown { keycode: Some(Keycode::I), .. } => {
+                    show_counter = !show_counter;
+                },
+                // like 00E0 but without the program knowing, shows what gets redrawn.
+                // not C, that one is taken by the keypad
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+                    chip.clear_display();
+                },
+                Event::KeyDown { keycode: Some(Keycode::F2), .. } => {
+                    match recorder.take() {
+                        Some(recorder) => finish_recording(recorder),
+                        None => {
+                            let path = keyrec::recording_path(&options.rom_path);
+                            println!("recording keys to '{path}', F2 stops");
+                            recorder = Some(Recorder::new(&path, frame));
+                        }
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F11), .. } => {
+                    toggle_fullscreen(&mut canvas);
+                    layout = Layout::new(&canvas, &chip, options);
+                    cache = cache.is_some().then(|| CellCache::new(&texture_creator, layout));
+                },
+                Event::Window { win_event: WindowEvent::Resized(..) | WindowEvent::SizeChanged(..), .. } => {
+                    layout = Layout::new(&canvas, &chip, options);
+                    cache = cache.is_some().then(|| CellCache::new(&texture_creator, layout));
+                },
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    println!("press: {key}");
+                    if let Some(idx) = key_index(key_map, key) {
+                        keypad.set(idx as u8, true);
+                        if let Some(recorder) = recorder.as_mut() {
+                            recorder.record(frame, idx as u8, true);
                         }
                     }
                     //if let Some(key) = keycode.and_then(|key| u8::from_str_radix(&key.to_string(), 16).ok()) {
@@ -125,51 +843,274 @@ pub fn spawn_window(mut chip: Chip) {
                     //}
                 },
                 Event::KeyUp { keycode, .. } => {
-                    if let Some(key) = keycode.map(|key| key.to_string()) {
-                        if let Some(idx) = KEY_MAP.iter().position(|x| key.eq(x)) {
-                            key_matrix[idx] = false;
+                    if let Some(idx) = keycode.and_then(|key| key_index(key_map, key)) {
+                        keypad.set(idx as u8, false);
+                        if let Some(recorder) = recorder.as_mut() {
+                            recorder.record(frame, idx as u8, false);
                         }
                     }
                 },
                 _ => {}
             }
         }
+        let mut step_frame = false;
         if p {
-            pause(&mut event_pump);
+            p = false;
+            set_title(&mut canvas, options, Some("PAUSED"));
+            match pause(&mut event_pump, &mut chip) {
+                Resume::Quit => break 'running,
+                Resume::StepFrame => step_frame = true,
+                Resume::Run => set_title(&mut canvas, options, None),
+            }
+            // don't try to make up for the time spent paused
+            pacer = Pacer::new(options);
+            last_input = Instant::now();
+        }
+
+        // holding a key down counts as playing too
+        if keypad.any_pressed() {
+            last_input = Instant::now();
+        }
+        if options.attract.is_some_and(|secs| !attract && last_input.elapsed().as_secs() >= secs) {
+            println!("attract: no input for {}s, running the demo", last_input.elapsed().as_secs());
+            attract = true;
+            chip = chip.fresh();
+            chip.load_bytes(&DEMO_ROM);
         }
 
-        canvas.set_draw_color(Color::RGB(18, 18, 18));
+        if let Some(player) = player.as_mut() {
+            player.apply(frame, &mut keypad);
+        }
+        if let Some(key) = keypad.take_press() {
+            chip.press_key(key);
+        }
+
+        if let Some(autosave) = autosave.as_mut() {
+            autosave.tick(&chip);
+        }
+
+        if let Some(watcher) = watcher.as_mut().filter(|w| w.changed()) {
+            watcher.reload(&mut chip);
+        }
+
+        // straight into memory, they aren't the program's writes
+        for &(addr, value) in &options.cheats {
+            chip.memory[addr as usize] = value;
+        }
+
+        canvas.set_draw_color(bg);
         canvas.clear();
 
         // println!("{key_matrix:#?}");
 
-        for _ in 0..CYCLES_PER_FRAME {
-            match chip.cycle() {
-                Err(ChipException::WaitForKey { register }) => wait_for_key(&mut chip, register, &mut event_pump),
+        let speed = if fast_forward && !step_frame { FAST_FORWARD_FACTOR } else { 1 };
+        // a stepped frame gets exactly one frame's worth, not whatever real time passed
+        let cycles = if step_frame {
+            (options.cpu_hz() / 60).max(1)
+        } else {
+            pacer.cycles_this_frame() * speed
+        };
+        // with authentic timing the frame ends once a VIP would have run out of time instead
+        let machine_cycles_end = chip.machine_cycles + timing::VIP_CYCLES_PER_FRAME * speed as u64;
+        let frame_done = |chip: &Chip, executed: u32| if options.authentic_timing {
+            chip.machine_cycles >= machine_cycles_end
+        } else {
+            executed >= cycles
+        };
+
+        let mut executed = 0;
+        let mut collided = false;
+        // for --vip-snow
+        let mut drew = false;
+        while chip.key_wait.is_none() && !frame_done(&chip, executed) {
+            if run_to_cycle == Some(chip.cycles) {
+                println!("run-to-cycle: paused after {} instructions", chip.cycles);
+                run_to_cycle = None;
+                p = true;
+                break;
+            }
+            executed += 1;
+            match chip.step() {
+                // ip is already past FX0A, the rest of the frame is spent waiting
+                Err(ChipException::WaitForKey { register }) => {
+                    chip.key_wait = Some(register);
+                }
                 Err(ChipException::SkipIfPressed { register }) => {
-                    if key_matrix[chip.data_regs[register as usize] as usize] {
+                    if keypad.is_pressed(chip.data_regs[register as usize]) {
                         chip.ip += 2;
                     }
                 }
                 Err(ChipException::SkipIfNotPressed { register }) => {
-                    if !key_matrix[chip.data_regs[register as usize] as usize] {
+                    if !keypad.is_pressed(chip.data_regs[register as usize]) {
                         chip.ip += 2;
                     }
                 }
+                Err(ChipException::SoundStarted { value }) => {
+                    println!("break-on-sound: ST = {value} at 0x{:03X}", chip.ip - 2);
+                    p = true;
+                    break;
+                }
+                Err(ChipException::Exit) => {
+                    println!("program exited (00FD)");
+                    set_title(&mut canvas, options, Some("EXITED"));
+                    // show the final frame, it's not drawn yet
+                    canvas.set_draw_color(fg);
+                    draw_grid(&mut canvas, &chip, options, layout, hud, display.as_mut(), cache.as_mut());
+                    canvas.present();
+                    freeze(&mut event_pump);
+                    break 'running
+                }
                 Err(e) => {
                     println!("chip8 runtime exception: {e:?}");
-                    freeze(event_pump);
+                    if let Some(dir) = &options.crash_dump {
+                        crash::dump(dir, &chip, &e);
+                    }
+                    set_title(&mut canvas, options, Some("HALTED"));
+                    freeze(&mut event_pump);
+                    break 'running
                 }
-                Ok(()) => {},
+                Ok(opcode) => {
+                    // DXYN leaves VF at 1 when it erased a pixel
+                    collided |= options.collision_beep && opcode >> 12 == 0xD && chip.data_regs[0xF] == 1;
+                    drew |= opcode >> 12 == 0xD;
+                },
             }
         }
 
-        canvas.set_draw_color(Color::RGB(255,255,255));
-        draw_grid(&mut canvas, &chip);
+        canvas.set_draw_color(fg);
+        draw_grid(&mut canvas, &chip, options, layout, hud, display.as_mut(), cache.as_mut());
+        if options.vip_snow && drew {
+            draw_snow(&mut canvas, &chip, options, layout);
+        }
+        if show_keypad {
+            draw_keypad(&mut canvas, &keypad, layout);
+        }
+        draw_heatmap(&mut canvas, &chip, layout);
+        chip.cool_down();
+        if show_counter {
+            draw_counter(&mut canvas, chip.cycles, started.elapsed().as_secs(), layout);
+        }
 
         canvas.present();
-        chip.delay_timer = chip.delay_timer.saturating_sub(1);
-        chip.sound_timer = chip.delay_timer.saturating_sub(1);
+        if let Some(frame_dump) = frame_dump.as_mut() {
+            frame_dump.tick(&chip, options);
+        }
+        chip.tick_timers();
+        if let Some(beeper) = beeper.as_mut() {
+            beeper.update(chip.sound_timer);
+            if collided {
+                beeper.click();
+            }
+        }
+        p |= step_frame;
+        frame += 1;
+        if options.frames == Some(frame) {
+            break 'running
+        }
         thread::sleep(Duration::from_millis(1000 / 60));
     }
+
+    shutdown(&chip, options, recorder);
+}
+
+// every way of closing the window ends up here, anything that writes files on exit
+// belongs in here rather than after a process::exit somewhere in the event handling
+fn shutdown(chip: &Chip, options: &Options, recorder: Option<Recorder>) {
+    if let Some(recorder) = recorder {
+        finish_recording(recorder);
+    }
+    if let Some(path) = &options.save_state {
+        match fs::write(path, chip.save_state()) {
+            Ok(()) => println!("saved state to '{path}'"),
+            Err(e) => eprintln!("Couldn't write '{path}' - {e}"),
+        }
+    }
+    if let Some(path) = &options.rng_record {
+        chip.rng.save(path);
+    }
+    if let (Some(path), Some(graph)) = (&options.call_graph, &chip.call_graph) {
+        match fs::write(path, graph.to_dot()) {
+            Ok(()) => println!("saved the call graph to '{path}'"),
+            Err(e) => eprintln!("Couldn't write '{path}' - {e}"),
+        }
+    }
+
+    let _ = io::stdout().flush();
+}
+
+fn finish_recording(recorder: Recorder) {
+    match recorder.finish() {
+        Ok(path) => println!("saved key recording to '{path}'"),
+        Err(e) => eprintln!("Couldn't write the key recording - {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(width: u32, height: u32, cell: u32) -> Layout {
+        Layout { x: 10, y: 20, cell_width: cell, cell_height: cell, width, height, cols: 64, rows: 32 }
+    }
+
+    #[test]
+    fn cell_rects_follow_whole_cells() {
+        let layout = layout(640, 320, 10);
+        assert_eq!(layout.cell_rect(0, 0), Rect::new(10, 20, 10, 10));
+        assert_eq!(layout.cell_rect(63, 31), Rect::new(640, 330, 10, 10));
+    }
+
+    #[test]
+    fn cell_rects_cover_a_stretched_display() {
+        // 1000 / 64 = 15.625 output pixels per cell, the way --texture scales
+        let layout = layout(1000, 500, 15);
+        assert_eq!(layout.cell_rect(63, 31), Rect::new(10 + 984, 20 + 484, 16, 16));
+        // no gaps or overlaps between neighbours
+        for col in 0..63 {
+            let (a, b) = (layout.cell_rect(col, 0), layout.cell_rect(col + 1, 0));
+            assert_eq!(a.x() + a.width() as i32, b.x());
+        }
+    }
+
+    #[test]
+    fn layouts_leave_the_frontend_keys_alone() {
+        for (name, key_map) in KEYPADS {
+            assert_eq!(frontend_key_conflicts(key_map), Vec::<&str>::new(), "{name}");
+        }
+    }
+
+    #[test]
+    fn conflicts_are_reported() {
+        let mut key_map = KEY_MAP;
+        key_map[0xD] = "P";
+        key_map[0x0] = "Tab";
+        assert_eq!(frontend_key_conflicts(&key_map), ["P", "Tab"]);
+    }
+
+    #[test]
+    fn two_player_layout_has_no_duplicates() {
+        let mut keys = TWO_PLAYER_KEY_MAP.to_vec();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 16);
+    }
+
+    #[test]
+    fn a_broken_rom_is_only_reported_once() {
+        let path = std::env::temp_dir().join(format!("chip8-watch-{}.ch8", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, [0x12, 0x00]).unwrap();
+        let mut watcher = RomWatcher::new(path);
+        let mut chip = Chip::default();
+
+        // too large to load. the rewrite can land within the same mtime tick, so
+        // forget the old time to be sure it counts as a change
+        fs::write(path, vec![0; 4096]).unwrap();
+        watcher.modified = None;
+        assert!(watcher.changed());
+        watcher.reload(&mut chip);
+        let changed = watcher.changed();
+        fs::remove_file(path).unwrap();
+        assert!(!changed);
+    }
 }
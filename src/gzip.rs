@@ -0,0 +1,349 @@
+// gzip support for load_program, so ROMs from .gz archives load without unpacking them
+// first. a small inflate along the lines of zlib's puff.c, fine for files the size of
+// a ROM. only the first member of the file is read
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// header flags
+const FHCRC: u8 = 2;
+const FEXTRA: u8 = 4;
+const FNAME: u8 = 8;
+const FCOMMENT: u8 = 16;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// the order the code length code lengths of a dynamic block come in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+// the unpacked contents, an error as soon as they grow past max_len
+pub fn decompress(data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+    if !is_gzip(data) || data.len() < 10 {
+        return Err("not a gzip file".to_string());
+    }
+    if data[2] != 8 {
+        return Err(format!("unknown compression method {}", data[2]));
+    }
+
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = data.get(pos..pos + 2).ok_or("truncated header")?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    // zero terminated strings
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            pos += data.get(pos..).and_then(|rest| rest.iter().position(|&b| b == 0)).ok_or("truncated header")? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+
+    let mut reader = BitReader { data: data.get(pos..).ok_or("truncated header")?, pos: 0, bit_buf: 0, bit_count: 0 };
+    let out = inflate(&mut reader, max_len)?;
+
+    let trailer = data.get(pos + reader.pos..pos + reader.pos + 8).ok_or("missing the gzip trailer")?;
+    let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+    if crc != crc32(&out) || size != out.len() as u32 {
+        return Err("corrupt data, the checksum doesn't match".to_string());
+    }
+    Ok(out)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// deflate packs its values starting at the lowest bit of every byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    // next byte to load, everything before it is used up once the stream ends
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.bit_count < n {
+            let byte = *self.data.get(self.pos).ok_or("unexpected end of the compressed data")?;
+            self.pos += 1;
+            self.bit_buf |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buf & ((1 << n) - 1);
+        self.bit_buf >>= n;
+        self.bit_count -= n;
+        Ok(value)
+    }
+
+    // stored blocks start at the next whole byte
+    fn align(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(self.bits(16)? as u16)
+    }
+}
+
+// a canonical Huffman code: how many codes there are of every length and the symbols
+// sorted by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate().filter(|(_, &len)| len != 0) {
+            symbols[offsets[len as usize] as usize] = symbol as u16;
+            offsets[len as usize] += 1;
+        }
+        Self { counts, symbols }
+    }
+
+    // one bit at a time, the codes of every length follow right after the shorter ones
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+fn inflate(reader: &mut BitReader, max_len: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => stored(reader, &mut out)?,
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                codes(reader, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]), max_len)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(reader)?;
+                codes(reader, &mut out, &literals, &distances, max_len)?;
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+        if out.len() > max_len {
+            return Err(format!("it unpacks to more than {max_len} bytes"));
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn stored(reader: &mut BitReader, out: &mut Vec<u8>) -> Result<(), String> {
+    reader.align();
+    let len = reader.u16()?;
+    if reader.u16()? != !len {
+        return Err("corrupt stored block".to_string());
+    }
+    let bytes = reader.data.get(reader.pos..reader.pos + len as usize).ok_or("unexpected end of the compressed data")?;
+    out.extend_from_slice(bytes);
+    reader.pos += len as usize;
+    Ok(())
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let n_literals = reader.bits(5)? as usize + 257;
+    let n_distances = reader.bits(5)? as usize + 1;
+    let n_code_lengths = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..n_code_lengths] {
+        code_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    // literal/length and distance code lengths in one go, repeats may cross between them
+    let mut lengths = Vec::with_capacity(n_literals + n_distances);
+    while lengths.len() < n_literals + n_distances {
+        let (len, repeat) = match code_lengths.decode(reader)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => (*lengths.last().ok_or("repeat without a previous length")?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths.len() > n_literals + n_distances {
+        return Err("code lengths repeat past the end".to_string());
+    }
+
+    Ok((Huffman::new(&lengths[..n_literals]), Huffman::new(&lengths[n_literals..])))
+}
+
+fn codes(reader: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman, max_len: usize) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err("invalid length code".to_string());
+                }
+                let len = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let symbol = distances.decode(reader)? as usize;
+                if symbol >= DIST_BASE.len() {
+                    return Err("invalid distance code".to_string());
+                }
+                let dist = DIST_BASE[symbol] as usize + reader.bits(DIST_EXTRA[symbol] as u32)? as usize;
+                if dist > out.len() {
+                    return Err("distance reaches back before the start".to_string());
+                }
+                // the copy may overlap what it's writing, so byte by byte
+                for _ in 0..len {
+                    out.push(out[out.len() - dist]);
+                }
+            }
+        }
+        // no point unpacking the rest of something that won't fit anyway
+        if out.len() > max_len {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // made with python's gzip.compress(data, level, mtime=0)
+    const STORED: [u8; 29] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03, 0x01, 0x06, 0x00, 0xf9, 0xff, 0x00,
+        0xe0, 0xa2, 0x0a, 0x60, 0x0c, 0xa8, 0x15, 0xa8, 0x1f, 0x06, 0x00, 0x00, 0x00,
+    ];
+    const STORED_DATA: &[u8] = b"\x00\xe0\xa2\x0a\x60\x0c";
+
+    const FIXED: [u8; 35] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x63, 0x78, 0xb0, 0x88, 0x2b, 0x81,
+        0x27, 0x91, 0xe3, 0x82, 0xa8, 0x10, 0x17, 0x03, 0x12, 0x1b, 0x00, 0x62, 0x5b, 0x55, 0xa7, 0x18,
+        0x00, 0x00, 0x00,
+    ];
+    const FIXED_DATA: &[u8] = b"\x00\xe0\xa2\x0a\x60\x0c\x61\x08\xd0\x15\x12\x0a\x00\xe0\xa2\x0a\x60\x0c\x61\x08\xd0\x15\x12\x0a";
+
+    const DYNAMIC: [u8; 94] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x25, 0x8d, 0xc1, 0x09, 0xc0, 0x30,
+        0x0c, 0x03, 0x57, 0xd1, 0x00, 0x59, 0xaa, 0x05, 0x83, 0x1f, 0x81, 0x42, 0xd5, 0x4f, 0xb7, 0xef,
+        0xc9, 0x05, 0xdd, 0xc9, 0x8f, 0xc4, 0x76, 0x57, 0xc9, 0x72, 0x6d, 0xf2, 0x23, 0x8f, 0xea, 0xc0,
+        0x14, 0x83, 0x88, 0x3b, 0xd4, 0x1e, 0x6d, 0x63, 0x2a, 0x0f, 0xc9, 0xf9, 0x06, 0x3d, 0xa3, 0x2e,
+        0x4c, 0x31, 0x88, 0x38, 0xfb, 0xf9, 0x3f, 0x72, 0xe3, 0x23, 0xf7, 0xfa, 0x22, 0x77, 0x85, 0xa5,
+        0x11, 0x2c, 0x65, 0xbb, 0xfc, 0x01, 0x1f, 0x49, 0xf4, 0x71, 0x95, 0x00, 0x00, 0x00,
+    ];
+    const DYNAMIC_DATA: &[u8] = b"shee s selsellsells slls seals  sesea ea sha shel shellssheelllls ls bys by t by \
+theby  ththe he see sea  sea shseaa s shoshorehore, ore, re,,  she s";
+
+    // the block type sits in bits 1-2 of the first byte after the 10 byte header
+    fn block_type(data: &[u8]) -> u8 {
+        (data[10] >> 1) & 3
+    }
+
+    #[test]
+    fn stored_block() {
+        assert_eq!(block_type(&STORED), 0);
+        assert_eq!(decompress(&STORED, 4096).unwrap(), STORED_DATA);
+    }
+
+    #[test]
+    fn fixed_block() {
+        assert_eq!(block_type(&FIXED), 1);
+        assert_eq!(decompress(&FIXED, 4096).unwrap(), FIXED_DATA);
+    }
+
+    #[test]
+    fn dynamic_block() {
+        assert_eq!(block_type(&DYNAMIC), 2);
+        assert_eq!(decompress(&DYNAMIC, 4096).unwrap(), DYNAMIC_DATA);
+    }
+
+    #[test]
+    fn skips_the_file_name() {
+        let mut data = STORED[..10].to_vec();
+        data[3] = FNAME;
+        data.extend_from_slice(b"pong.ch8\0");
+        data.extend_from_slice(&STORED[10..]);
+        assert_eq!(decompress(&data, 4096).unwrap(), STORED_DATA);
+    }
+
+    #[test]
+    fn truncated_stream() {
+        for len in [5, 12, 40, DYNAMIC.len() - 4] {
+            assert!(decompress(&DYNAMIC[..len], 4096).is_err(), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn bad_crc() {
+        let mut data = FIXED;
+        data[FIXED.len() - 8] ^= 1;
+        assert_eq!(decompress(&data, 4096).unwrap_err(), "corrupt data, the checksum doesn't match");
+    }
+
+    #[test]
+    fn bad_size() {
+        let mut data = FIXED;
+        data[FIXED.len() - 4] += 1;
+        assert_eq!(decompress(&data, 4096).unwrap_err(), "corrupt data, the checksum doesn't match");
+    }
+
+    #[test]
+    fn too_large() {
+        assert_eq!(decompress(&DYNAMIC, 100).unwrap_err(), "it unpacks to more than 100 bytes");
+    }
+
+    #[test]
+    fn not_gzip() {
+        assert!(!is_gzip(b"\x12\x00"));
+        assert_eq!(decompress(b"\x12\x00\x12\x00", 4096).unwrap_err(), "not a gzip file");
+    }
+}
@@ -0,0 +1,464 @@
+use crate::{crash, debugger, disasm, keyrec, timing, xbm, Chip, ChipException, Options, EXIT_ERROR, EXIT_EXCEPTION};
+use crate::frames::FrameDump;
+use crate::keypad::Keypad;
+use crate::keyrec::Player;
+use crate::savestate::Autosave;
+
+use std::collections::BTreeSet;
+use std::process::ExitCode;
+use std::time::Instant;
+
+// why the headless run stopped
+pub enum Halt {
+    MaxCycles,
+    // the program ran 00FD
+    Exited,
+    Exception(ChipException),
+}
+
+// how many frames FX0A waits for a --play key before carrying on with key 0,
+// a second of emulated time
+pub const DEFAULT_KEY_TIMEOUT: u64 = 60;
+
+// what Chip::run_with_hook needs to know besides the machine itself. execute fills it
+// in from the options, the defaults are those of a plain --headless run
+pub struct RunSettings {
+    // instructions per 60 Hz frame, unless authentic_timing counts VIP machine cycles
+    pub cycles_per_frame: u64,
+    pub authentic_timing: bool,
+    // stop once this many instructions ran, None runs until an exception or 00FD
+    pub max_cycles: Option<u64>,
+    // frames FX0A waits before it gets key 0
+    pub key_timeout: u64,
+    // the keys to press, for --play
+    pub play: Option<Vec<keyrec::KeyEvent>>,
+}
+
+impl Default for RunSettings {
+    fn default() -> Self {
+        Self::new(&Options::default())
+    }
+}
+
+impl RunSettings {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            cycles_per_frame: (options.cpu_hz() / 60).max(1) as u64,
+            authentic_timing: options.authentic_timing,
+            max_cycles: options.max_cycles,
+            key_timeout: options.key_timeout(),
+            play: options.play.clone(),
+        }
+    }
+}
+
+// the keypad of a headless run, only --play ever presses anything
+struct Input {
+    // frames so far, including the ones spent waiting for a key
+    frame: u64,
+    keypad: Keypad,
+    player: Option<Player>,
+}
+
+impl Input {
+    fn new(settings: &RunSettings) -> Self {
+        let player = settings.play.as_ref().map(|events| Player::new(events.clone()));
+        Self { frame: 0, keypad: Keypad::default(), player }
+    }
+
+    // the keys that go down this frame, a press ends an FX0A wait
+    fn apply(&mut self, chip: &mut Chip) {
+        if let Some(player) = self.player.as_mut() {
+            player.apply(self.frame, &mut self.keypad);
+        }
+        if let Some(key) = self.keypad.take_press() {
+            chip.press_key(key);
+        }
+    }
+
+    fn next_frame(&mut self, chip: &mut Chip) {
+        chip.tick_timers();
+        self.frame += 1;
+        self.apply(chip);
+    }
+
+    fn pressed(&self, key: u8) -> bool {
+        self.keypad.is_pressed(key)
+    }
+}
+
+// run the chip without a window until it either hits max_cycles or raises an exception.
+// keys come from --play if given, otherwise every key reads as released. FX0A gets key 0
+// once it has waited key_timeout frames, so nothing waits forever
+fn execute(chip: &mut Chip, options: &Options) -> Halt {
+    let mut autosave = Autosave::new(options);
+    let mut frame_dump = FrameDump::new(options);
+    chip.run_with_hook(&RunSettings::new(options), |chip, _| {
+        if let Some(autosave) = autosave.as_mut() {
+            autosave.tick(chip);
+        }
+        if let Some(frame_dump) = frame_dump.as_mut() {
+            frame_dump.tick(chip, options);
+        }
+    })
+}
+
+impl Chip {
+    // a headless run as set up in settings, with a look at the machine between frames
+    // for anything that wants to check or change state as the program runs. the hook
+    // gets the frame number (the first frame is 1) after that frame's timer tick and
+    // --play keys, right before the next frame's first instruction. frames spent
+    // waiting in FX0A count too, the key 0 from key_timeout comes after the hook
+    pub fn run_with_hook(&mut self, settings: &RunSettings, mut hook: impl FnMut(&mut Chip, usize)) -> Halt {
+        let frame = |chip: &Chip| if settings.authentic_timing {
+            chip.machine_cycles / timing::VIP_CYCLES_PER_FRAME
+        } else {
+            chip.cycles / settings.cycles_per_frame
+        };
+        let mut last_frame = frame(self);
+        let mut input = Input::new(settings);
+        input.apply(self);
+        let mut waited = 0;
+
+        loop {
+            if settings.max_cycles.is_some_and(|max| self.cycles >= max) {
+                break Halt::MaxCycles;
+            }
+
+            // nothing runs while FX0A waits, only the frames go by
+            if self.key_wait.is_some() {
+                waited += 1;
+                input.next_frame(self);
+                hook(self, input.frame as usize);
+                if self.key_wait.is_some() && waited >= settings.key_timeout {
+                    println!("FX0A at 0x{:03X} waited {waited} frames, pressing key 0", self.ip - 2);
+                    self.press_key(0);
+                }
+                continue;
+            }
+
+            match self.cycle() {
+                Err(ChipException::SkipIfPressed { register }) => {
+                    if input.pressed(self.data_regs[register as usize]) {
+                        self.ip += 2;
+                    }
+                }
+                Err(ChipException::SkipIfNotPressed { register }) => {
+                    if !input.pressed(self.data_regs[register as usize]) {
+                        self.ip += 2;
+                    }
+                }
+                Err(ChipException::WaitForKey { register }) => {
+                    self.key_wait = Some(register);
+                    waited = 0;
+                }
+                // there is no debugger to drop into, just report it
+                Err(ChipException::SoundStarted { value }) => {
+                    println!("break-on-sound: ST = {value} at 0x{:03X}", self.ip - 2);
+                }
+                Err(ChipException::Exit) => break Halt::Exited,
+                Err(e) => break Halt::Exception(e),
+                Ok(()) => {}
+            }
+
+            // keep the timers running at the same rate as in the window
+            if frame(self) != last_frame {
+                last_frame = frame(self);
+                input.next_frame(self);
+                hook(self, input.frame as usize);
+            }
+        }
+    }
+}
+
+pub fn run(mut chip: Chip, options: &Options) -> ExitCode {
+    let halt = execute(&mut chip, options);
+    print_summary(&chip, &halt, options.json, None);
+
+    if let (Halt::Exception(e), Some(dir)) = (&halt, &options.crash_dump) {
+        crash::dump(dir, &chip, e);
+    }
+
+    if let Some((addr, rows)) = options.preview_sprite {
+        for line in debugger::sprite_preview(&chip, addr, rows) {
+            println!("{line}");
+        }
+    }
+
+    if options.print_display {
+        print!("{}", chip.framebuffer_ascii());
+    }
+
+    if let Some(path) = &options.export_xbm {
+        let image = xbm::emit(&chip.video_memory, chip.screen_width, chip.screen_height);
+        if let Err(e) = std::fs::write(path, image) {
+            eprintln!("Couldn't write '{path}' - {e}");
+        }
+    }
+
+    if let Some(path) = &options.export_header {
+        if let Err(e) = std::fs::write(path, chip.to_c_header(options.export_header_memory)) {
+            eprintln!("Couldn't write '{path}' - {e}");
+        }
+    }
+
+    if let Some(path) = &options.save_state {
+        if let Err(e) = std::fs::write(path, chip.save_state()) {
+            eprintln!("Couldn't write '{path}' - {e}");
+        }
+    }
+
+    if let Some(path) = &options.rng_record {
+        chip.rng.save(path);
+    }
+    if let (Some(path), Some(graph)) = (&options.call_graph, &chip.call_graph) {
+        if let Err(e) = std::fs::write(path, graph.to_dot()) {
+            eprintln!("Couldn't write '{path}' - {e}");
+        }
+    }
+
+    #[cfg(feature = "json")]
+    if let Some(path) = &options.dump_json {
+        if let Err(e) = std::fs::write(path, chip.to_json(options.dump_json_memory)) {
+            eprintln!("Couldn't write '{path}' - {e}");
+        }
+    }
+
+    exit_code(&halt)
+}
+
+fn exit_code(halt: &Halt) -> ExitCode {
+    match halt {
+        Halt::MaxCycles | Halt::Exited => ExitCode::SUCCESS,
+        Halt::Exception(_) => ExitCode::from(EXIT_EXCEPTION),
+    }
+}
+
+// run each ROM from a clean machine with the same settings, then list the
+// opcodes none of them executed. useful for checking a test corpus is complete
+// exits with the runtime exception code if any ROM hit one, a ROM that doesn't load
+// at all counts as an error
+pub fn run_batch(template: &Chip, options: &Options) -> ExitCode {
+    let mut covered = BTreeSet::new();
+    let mut load_failed = false;
+    let mut exception = false;
+
+    for rom in &options.batch_roms {
+        let mut chip = template.fresh();
+        chip.coverage = Some(BTreeSet::new());
+
+        if let Err(e) = chip.load_program(rom) {
+            eprintln!("Couldn't load '{rom}' - {e}");
+            load_failed = true;
+            continue;
+        }
+
+        let halt = execute(&mut chip, options);
+        exception |= matches!(halt, Halt::Exception(_));
+        print_summary(&chip, &halt, options.json, Some(rom));
+        covered.extend(chip.coverage.unwrap_or_default());
+    }
+
+    let missing: Vec<_> = disasm::OPCODES.iter()
+        .map(|info| info.pattern)
+        .filter(|pattern| !covered.contains(pattern))
+        .collect();
+
+    println!("opcode coverage: {}/{} patterns executed", covered.len(), disasm::OPCODES.len());
+    if !missing.is_empty() {
+        println!("never executed: {}", missing.join(" "));
+    }
+
+    if load_failed {
+        ExitCode::from(EXIT_ERROR)
+    } else if exception {
+        ExitCode::from(EXIT_EXCEPTION)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+// how often --bench runs the workload
+const BENCH_RUNS: usize = 5;
+
+// run the program for the given number of cycles a few times, each from the same
+// starting state, and report how fast the interpreter got through it
+pub fn run_bench(template: &Chip, options: &Options, cycles: u64) -> ExitCode {
+    let bench_options = Options {
+        max_cycles: Some(cycles),
+        cpu_hz: options.cpu_hz,
+        authentic_timing: options.authentic_timing,
+        ..Options::default()
+    };
+
+    let mut rates = Vec::new();
+    for _ in 0..BENCH_RUNS {
+        let mut chip = template.clone();
+        let start = Instant::now();
+        let halt = execute(&mut chip, &bench_options);
+        let elapsed = start.elapsed().as_secs_f64();
+
+        match halt {
+            Halt::MaxCycles => {}
+            Halt::Exited => {
+                eprintln!("bench: the program exited after {} cycles", chip.cycles);
+                return ExitCode::from(EXIT_ERROR);
+            }
+            Halt::Exception(e) => {
+                eprintln!("bench: the program halted after {} cycles - {e:?}", chip.cycles);
+                return ExitCode::from(EXIT_EXCEPTION);
+            }
+        }
+        rates.push(cycles as f64 / elapsed);
+    }
+
+    rates.sort_by(f64::total_cmp);
+    println!("bench: {BENCH_RUNS} runs of {cycles} cycles, instructions per second: min {:.0}, median {:.0}, max {:.0}",
+             rates[0], rates[BENCH_RUNS / 2], rates[BENCH_RUNS - 1]);
+    ExitCode::SUCCESS
+}
+
+fn halt_reason(halt: &Halt) -> (&'static str, Option<String>) {
+    match halt {
+        Halt::MaxCycles => ("max-cycles", None),
+        Halt::Exited => ("exit", None),
+        Halt::Exception(e) => ("exception", Some(format!("{e:?}"))),
+    }
+}
+
+// a single line that scripts can pick apart
+fn print_summary(chip: &Chip, halt: &Halt, json: bool, rom: Option<&str>) {
+    let (reason, exception) = halt_reason(halt);
+
+    if json {
+        println!("{}", summary_json(chip, reason, exception, rom));
+    } else {
+        let rom = rom.map_or(String::new(), |rom| format!("rom=\"{rom}\" "));
+        let exception = exception.map_or(String::new(), |e| format!(" exception=\"{e}\""));
+        println!("{rom}cycles={} ip=0x{:03X} halt={reason}{exception} framebuffer_hash={:016x}",
+                 chip.cycles, chip.ip, chip.framebuffer_hash());
+    }
+}
+
+// quoted and escaped, file names can hold anything
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn summary_json(chip: &Chip, reason: &str, exception: Option<String>, rom: Option<&str>) -> String {
+    let rom = rom.map_or(String::new(), |rom| format!("\"rom\":{},", json_string(rom)));
+    let exception = exception.map_or("null".to_string(), |e| format!("\"{e}\""));
+    format!("{{{rom}\"cycles\":{},\"ip\":{},\"halt\":\"{reason}\",\"exception\":{exception},\"framebuffer_hash\":\"{:016x}\"}}",
+            chip.cycles, chip.ip, chip.framebuffer_hash())
+}
+
+// one --serve request: cycles instructions of rom on a fresh machine with the
+// template's settings. the summary json with the machine state added as "state"
+#[cfg(feature = "serve")]
+pub fn run_request(template: &Chip, options: &Options, rom: &[u8], cycles: u64) -> String {
+    let request_options = Options {
+        max_cycles: Some(cycles),
+        cpu_hz: options.cpu_hz,
+        authentic_timing: options.authentic_timing,
+        key_timeout: options.key_timeout,
+        ..Options::default()
+    };
+
+    let mut chip = template.fresh();
+    chip.load_bytes(rom);
+    let halt = execute(&mut chip, &request_options);
+    let (reason, exception) = halt_reason(&halt);
+    let summary = summary_json(&chip, reason, exception, None);
+    format!("{},\"state\":{}}}", summary.trim_end_matches('}'), chip.to_json(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::chip_with;
+
+    // a plain --headless run that stops after max_cycles
+    fn settings(max_cycles: u64) -> RunSettings {
+        RunSettings { max_cycles: Some(max_cycles), ..RunSettings::default() }
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("roms/pong.ch8"), "\"roms/pong.ch8\"");
+        assert_eq!(json_string("C:\\roms\\\"x\".ch8"), "\"C:\\\\roms\\\\\\\"x\\\".ch8\"");
+        assert_eq!(json_string("a\nb\tc"), "\"a\\nb\\u0009c\"");
+    }
+
+    #[test]
+    fn hook_runs_once_per_frame() {
+        // 1200: jump to itself
+        let mut chip = chip_with(&[0x12, 0x00]);
+        let mut frames = Vec::new();
+        let halt = chip.run_with_hook(&settings(100), |_, frame| frames.push(frame));
+        assert!(matches!(halt, Halt::MaxCycles));
+        // 20 instructions per frame at the default speed
+        assert_eq!(frames, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn hook_can_change_the_machine() {
+        // 7001 (V0 += 1), 1200 jumps back
+        let mut chip = chip_with(&[0x70, 0x01, 0x12, 0x00]);
+        chip.run_with_hook(&settings(40), |chip, frame| if frame == 1 {
+            chip.data_regs[1] = 0xAB;
+        });
+        assert_eq!(chip.data_regs[0], 20);
+        assert_eq!(chip.data_regs[1], 0xAB);
+    }
+
+    #[test]
+    fn key_wait_times_out_with_key_0() {
+        // F30A waits for a key into V3, then 1202 spins
+        let mut chip = chip_with(&[0xF3, 0x0A, 0x12, 0x02]);
+        chip.data_regs[3] = 0xFF;
+        let mut frames = 0;
+        chip.run_with_hook(&RunSettings { key_timeout: 3, ..settings(10) }, |_, _| frames += 1);
+        assert_eq!(chip.data_regs[3], 0);
+        assert_eq!(frames, 3);
+    }
+
+    #[test]
+    fn exit_stops_the_run() {
+        let mut chip = chip_with(&[0x00, 0xFD]);
+        assert!(matches!(chip.run_with_hook(&settings(1000), |_, _| {}), Halt::Exited));
+        assert_eq!(chip.cycles, 1);
+    }
+
+    #[test]
+    fn skips_see_the_played_keys_as_released() {
+        // V0 = 0x15, EXA1 skips when key 5 isn't down so V1 stays 0
+        let mut chip = chip_with(&[0x60, 0x15, 0xE0, 0xA1, 0x61, 0x01, 0x12, 0x06]);
+        chip.run_with_hook(&settings(10), |_, _| {});
+        assert_eq!(chip.data_regs[1], 0);
+    }
+
+    #[test]
+    fn key_skips_with_a_register_above_0xf() {
+        // V0 = 0xFF, EX9E skips the 6101 when key F is down
+        let rom = [0x60, 0xFF, 0xE0, 0x9E, 0x61, 0x01, 0x12, 0x06];
+        let mut released = chip_with(&rom);
+        released.run_with_hook(&settings(10), |_, _| {});
+        assert_eq!(released.data_regs[1], 1);
+
+        let mut held = chip_with(&rom);
+        let play = Some(vec![keyrec::KeyEvent { frame: 0, key: 0xF, down: true }]);
+        held.run_with_hook(&RunSettings { play, ..settings(10) }, |_, _| {});
+        assert_eq!(held.data_regs[1], 0);
+    }
+}
@@ -0,0 +1,90 @@
+// the machine state as json for --dump-json and --serve, the 'json' feature. written
+// by hand like everything else here, memory goes in as base64 so it stays one string
+
+use crate::Chip;
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+
+        for i in 0..4 {
+            // pad the last group if the chunk was short
+            if i > chunk.len() {
+                out.push('=');
+            } else {
+                out.push(BASE64_ALPHABET[((n >> (18 - 6 * i)) & 0x3F) as usize] as char);
+            }
+        }
+    }
+
+    out
+}
+
+fn json_array<T: ToString>(values: impl Iterator<Item = T>) -> String {
+    format!("[{}]", values.map(|v| v.to_string()).collect::<Vec<_>>().join(","))
+}
+
+impl Chip {
+    // dump the machine state for external tools,
+    // memory and video memory are only included (as base64) when asked for
+    pub fn to_json(&self, include_memory: bool) -> String {
+        let mut fields = vec![
+            format!("\"registers\":{}", json_array(self.data_regs.iter())),
+            format!("\"i\":{}", self.addr_reg),
+            format!("\"ip\":{}", self.ip),
+            format!("\"sp\":{}", self.stack.len()),
+            format!("\"stack\":{}", json_array(self.stack.iter())),
+            format!("\"delay_timer\":{}", self.delay_timer),
+            format!("\"sound_timer\":{}", self.sound_timer),
+            format!("\"cycles\":{}", self.cycles),
+        ];
+
+        if include_memory {
+            fields.push(format!("\"memory\":\"{}\"", base64(&self.memory[..])));
+            fields.push(format!("\"video_memory\":\"{}\"", base64(&self.video_memory[..])));
+        }
+
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_short_groups() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(&[0xFF, 0xFE, 0xFD]), "//79");
+    }
+
+    #[test]
+    fn state_without_memory() {
+        let mut chip = Chip::default();
+        chip.data_regs[0xF] = 1;
+        chip.addr_reg = 0x300;
+        chip.stack.push(0x204);
+        assert_eq!(chip.to_json(false), "{\"registers\":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],\"i\":768,\"ip\":512,\"sp\":1,\"stack\":[516],\"delay_timer\":0,\"sound_timer\":0,\"cycles\":0}");
+    }
+
+    #[test]
+    fn state_with_memory() {
+        let chip = Chip::default();
+        let json = chip.to_json(true);
+        // 4096 bytes leave one for the last group, 2048 pixels two
+        let memory = base64(&chip.memory[..]);
+        assert_eq!(memory.len(), 5464);
+        assert!(memory.ends_with("AA=="));
+        assert!(json.contains(&format!(",\"memory\":\"{memory}\",")));
+        assert!(json.ends_with(&format!(",\"video_memory\":\"{}AAA=\"}}", "A".repeat(2728))));
+    }
+}
@@ -0,0 +1,74 @@
+// the hex keypad as the frontends see it. besides which keys are held it remembers
+// the first one that went down since it was last asked, that's what ends an FX0A wait.
+// keys are whatever a register holds, only the low nibble counts so a ROM with a
+// larger value in VX can't take the emulator down
+
+#[derive(Default)]
+pub struct Keypad {
+    held: [bool; 16],
+    press: Option<u8>,
+}
+
+impl Keypad {
+    // a key that's already held doesn't go down again, so key repeat can't end a wait
+    pub fn set(&mut self, key: u8, down: bool) {
+        let key = key & 0xF;
+        let held = &mut self.held[key as usize];
+        if down && !*held && self.press.is_none() {
+            self.press = Some(key);
+        }
+        *held = down;
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.held[(key & 0xF) as usize]
+    }
+
+    pub fn any_pressed(&self) -> bool {
+        self.held.contains(&true)
+    }
+
+    // called once a frame, whether or not anything waits for it
+    pub fn take_press(&mut self) -> Option<u8> {
+        self.press.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_keys_use_the_low_nibble() {
+        let mut keypad = Keypad::default();
+        keypad.set(0x1F, true);
+        assert!(keypad.is_pressed(0xF));
+        assert!(keypad.is_pressed(0xFF));
+        assert!(!keypad.is_pressed(0x1));
+        assert_eq!(keypad.take_press(), Some(0xF));
+        keypad.set(0xFF, false);
+        assert!(!keypad.any_pressed());
+    }
+
+    #[test]
+    fn held_keys_dont_press_again() {
+        let mut keypad = Keypad::default();
+        keypad.set(5, true);
+        assert_eq!(keypad.take_press(), Some(5));
+        keypad.set(5, true);
+        assert_eq!(keypad.take_press(), None);
+        keypad.set(5, false);
+        keypad.set(5, true);
+        assert_eq!(keypad.take_press(), Some(5));
+    }
+
+    #[test]
+    fn the_first_press_wins() {
+        let mut keypad = Keypad::default();
+        keypad.set(2, true);
+        keypad.set(9, true);
+        assert_eq!(keypad.take_press(), Some(2));
+        assert_eq!(keypad.take_press(), None);
+        assert!(keypad.is_pressed(2) && keypad.is_pressed(9));
+    }
+}
@@ -0,0 +1,154 @@
+// recording keypad input (F2 in the window) and playing it back with --play.
+// the file is plain text, one event per line:
+//
+//     # frame key state
+//     12 5 down
+//     20 5 up
+//
+// frames count from the start of the recording, playback starts them at the first
+// frame of the run. key is the chip key in hex
+
+use crate::keypad::Keypad;
+
+use std::fs;
+use std::path::Path;
+
+// next to the rom, demo.keys for the built in demo
+pub fn recording_path(rom_path: &str) -> String {
+    if rom_path.is_empty() {
+        return "demo.keys".to_string();
+    }
+    Path::new(rom_path).with_extension("keys").to_string_lossy().into_owned()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub down: bool,
+}
+
+pub struct Recorder {
+    path: String,
+    start_frame: u64,
+    events: Vec<KeyEvent>,
+}
+
+impl Recorder {
+    pub fn new(path: &str, frame: u64) -> Self {
+        Self { path: path.to_string(), start_frame: frame, events: Vec::new() }
+    }
+
+    pub fn record(&mut self, frame: u64, key: u8, down: bool) {
+        self.events.push(KeyEvent { frame: frame - self.start_frame, key, down });
+    }
+
+    pub fn finish(self) -> Result<String, String> {
+        let mut text = String::from("# frame key state\n");
+        for event in &self.events {
+            text += &format!("{} {:X} {}\n", event.frame, event.key, if event.down { "down" } else { "up" });
+        }
+        fs::write(&self.path, text).map_err(|e| e.to_string())?;
+        Ok(self.path)
+    }
+}
+
+pub fn load(path: &str) -> Result<Vec<KeyEvent>, String> {
+    parse(&fs::read_to_string(path).map_err(|e| e.to_string())?)
+}
+
+fn parse(text: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut events = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let event = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [frame, key, state] => (|| Some(KeyEvent {
+                frame: frame.parse().ok()?,
+                key: u8::from_str_radix(key, 16).ok().filter(|&key| key < 16)?,
+                down: match *state { "down" => true, "up" => false, _ => return None },
+            }))(),
+            _ => None,
+        };
+        events.push(event.ok_or_else(|| format!("line {}: expected `frame key down|up`", n + 1))?);
+    }
+
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+// hands out the events of a recording as their frames come up
+pub struct Player {
+    events: Vec<KeyEvent>,
+    next: usize,
+}
+
+impl Player {
+    pub fn new(events: Vec<KeyEvent>) -> Self {
+        Self { events, next: 0 }
+    }
+
+    pub fn apply(&mut self, frame: u64, keypad: &mut Keypad) {
+        while let Some(event) = self.events.get(self.next).filter(|event| event.frame <= frame) {
+            keypad.set(event.key, event.down);
+            self.next += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(frame: u64, key: u8, down: bool) -> KeyEvent {
+        KeyEvent { frame, key, down }
+    }
+
+    #[test]
+    fn parses_a_recording() {
+        let text = "# frame key state\n\n20 a up\n  12 A down  \n# a comment\n3 0 down\n";
+        assert_eq!(parse(text), Ok(vec![event(3, 0, true), event(12, 0xA, true), event(20, 0xA, false)]));
+    }
+
+    #[test]
+    fn malformed_lines_are_refused() {
+        for line in ["12 5", "12 5 down now", "x 5 down", "-1 5 down", "12 10 down", "12 G down", "12 5 pressed"] {
+            assert_eq!(parse(&format!("# frame key state\n{line}\n")), Err("line 2: expected `frame key down|up`".to_string()), "{line}");
+        }
+    }
+
+    #[test]
+    fn recordings_load_back() {
+        let path = std::env::temp_dir().join(format!("chip8-keyrec-{}.keys", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut recorder = Recorder::new(path, 100);
+        recorder.record(100, 0xF, true);
+        recorder.record(130, 0xF, false);
+        recorder.finish().unwrap();
+        let events = load(path);
+        fs::remove_file(path).unwrap();
+        assert_eq!(events, Ok(vec![event(0, 0xF, true), event(30, 0xF, false)]));
+    }
+
+    #[test]
+    fn player_applies_events_as_their_frames_come() {
+        let mut player = Player::new(vec![event(0, 1, true), event(2, 1, false), event(2, 7, true)]);
+        let mut keypad = Keypad::default();
+        player.apply(0, &mut keypad);
+        assert!(keypad.is_pressed(1));
+        player.apply(1, &mut keypad);
+        assert!(keypad.is_pressed(1));
+        player.apply(5, &mut keypad);
+        assert!(!keypad.is_pressed(1) && keypad.is_pressed(7));
+    }
+
+    #[test]
+    fn recordings_sit_next_to_the_rom() {
+        assert_eq!(recording_path("roms/pong.ch8"), "roms/pong.keys");
+        assert_eq!(recording_path(""), "demo.keys");
+    }
+}
@@ -0,0 +1,171 @@
+// static checks for --lint. walks every instruction reachable from LOAD_ADDR by
+// following jumps, calls and skips, and complains about:
+//
+//     jumps and calls leaving the loaded program
+//     jumps and calls to odd addresses
+//     words that don't decode to an instruction
+//     sprites drawn from memory the ROM doesn't fill, when I was set by a plain ANNN
+//
+// computed jumps (BNNN) aren't followed and I is forgotten whenever anything but ANNN
+// changes it, so a clean run doesn't prove much. it catches the obvious stuff
+
+use crate::{disasm, FONT_DATA, LOAD_ADDR};
+
+use std::collections::BTreeSet;
+
+// (address, message) sorted by address
+pub fn lint(memory: &[u8], rom_len: usize) -> Vec<(u16, String)> {
+    let rom = LOAD_ADDR as usize..LOAD_ADDR as usize + rom_len;
+    let mut findings = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+    // (address, I if it's known)
+    let mut work = vec![(LOAD_ADDR, None)];
+
+    while let Some((addr, i)) = work.pop() {
+        if !visited.insert(addr) {
+            continue;
+        }
+        let at = addr as usize;
+        if !rom.contains(&at) || !rom.contains(&(at + 1)) {
+            findings.insert((addr, "execution runs off the end of the program".to_string()));
+            continue;
+        }
+
+        let instr = u16::from_be_bytes([memory[at], memory[at + 1]]);
+        let Some(info) = disasm::decode(instr) else {
+            findings.insert((addr, format!("{instr:04X} isn't an instruction")));
+            continue;
+        };
+        let (nnn, n) = (instr & 0xFFF, (instr & 0xF) as usize);
+        let next = addr + 2;
+
+        let target = |findings: &mut BTreeSet<_>, what: &str| {
+            if !rom.contains(&(nnn as usize)) {
+                findings.insert((addr, format!("{what} to 0x{nnn:03X}, outside the program (0x{:03X}-0x{:03X})", rom.start, rom.end - 1)));
+                false
+            } else if nnn % 2 != 0 {
+                findings.insert((addr, format!("{what} to odd address 0x{nnn:03X}")));
+                true
+            } else {
+                true
+            }
+        };
+
+        match info.pattern {
+            "1NNN" => {
+                if target(&mut findings, "jump") {
+                    work.push((nnn, i));
+                }
+            }
+            // SYS runs as a call too
+            "2NNN" | "0NNN" => {
+                if target(&mut findings, "call") {
+                    // whatever the subroutine does to I is lost
+                    work.push((nnn, i));
+                }
+                work.push((next, None));
+            }
+            "00EE" | "00FD" | "BNNN" => {}
+            "3XNN" | "4XNN" | "5XY0" | "9XY0" | "EX9E" | "EXA1" => {
+                work.push((next, i));
+                work.push((next + 2, i));
+            }
+            "ANNN" => work.push((next, Some(nnn))),
+            "DXYN" => {
+                if let Some(i) = i {
+                    let sprite = i as usize..i as usize + n;
+                    let filled = |at: usize| at < FONT_DATA.len() || rom.contains(&at);
+                    if !sprite.clone().all(filled) {
+                        findings.insert((addr, format!("draws the {n} byte sprite at 0x{i:03X}, which the ROM doesn't fill")));
+                    }
+                }
+                work.push((next, i));
+            }
+            // anything else that touches I
+            "FX1E" | "FX29" | "FX55" | "FX65" => work.push((next, None)),
+            _ => work.push((next, i)),
+        }
+    }
+
+    findings.into_iter().collect()
+}
+
+pub fn print(memory: &[u8], rom_len: usize) {
+    let findings = lint(memory, rom_len);
+    for (addr, message) in &findings {
+        println!("0x{addr:03X}: {message}");
+    }
+    match findings.len() {
+        0 => println!("no problems found"),
+        n => println!("{n} problem(s) found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the findings for a ROM loaded at LOAD_ADDR
+    fn lint_rom(rom: &[u8]) -> Vec<(u16, String)> {
+        let mut memory = [0; 4096];
+        memory[LOAD_ADDR as usize..LOAD_ADDR as usize + rom.len()].copy_from_slice(rom);
+        lint(&memory, rom.len())
+    }
+
+    #[test]
+    fn sys_is_followed_like_a_call() {
+        // SYS 0x206 and back, then loop. the subroutine is only reachable through SYS
+        let rom = [0x02, 0x06, 0x12, 0x02, 0x00, 0x00, 0x00, 0xEE];
+        assert!(lint_rom(&rom).is_empty(), "{:?}", lint_rom(&rom));
+        let outside = lint_rom(&[0x00, 0x10, 0x12, 0x02]);
+        assert_eq!(outside, vec![(0x200, "call to 0x010, outside the program (0x200-0x203)".to_string())]);
+    }
+
+    fn finding(addr: u16, message: &str) -> Vec<(u16, String)> {
+        vec![(addr, message.to_string())]
+    }
+
+    #[test]
+    fn unreachable_words_arent_checked() {
+        // a loop, then FFFF that nothing ever gets to
+        assert!(lint_rom(&[0x12, 0x00, 0xFF, 0xFF]).is_empty());
+    }
+
+    #[test]
+    fn jump_outside_the_program() {
+        assert_eq!(lint_rom(&[0x13, 0x00]), finding(0x200, "jump to 0x300, outside the program (0x200-0x201)"));
+    }
+
+    #[test]
+    fn jump_to_an_odd_address() {
+        // lands in the middle of the loop, 0x00 0x12 is then a SYS outside the program
+        let findings = lint_rom(&[0x12, 0x03, 0x12, 0x00, 0x12, 0x00]);
+        assert_eq!(findings[0], (0x200, "jump to odd address 0x203".to_string()));
+    }
+
+    #[test]
+    fn jump_into_data() {
+        // 1204 skips over the loop into FFFF
+        assert_eq!(lint_rom(&[0x12, 0x04, 0x12, 0x02, 0xFF, 0xFF]), finding(0x204, "FFFF isn't an instruction"));
+    }
+
+    #[test]
+    fn running_off_the_end() {
+        assert_eq!(lint_rom(&[0x60, 0x01]), finding(0x202, "execution runs off the end of the program"));
+    }
+
+    #[test]
+    fn sprite_the_rom_doesnt_fill() {
+        // I = 0x206 right past the end, draw 5 rows, loop
+        let rom = [0xA2, 0x06, 0xD0, 0x15, 0x12, 0x04];
+        assert_eq!(lint_rom(&rom), finding(0x202, "draws the 5 byte sprite at 0x206, which the ROM doesn't fill"));
+        // font glyphs are always there
+        assert!(lint_rom(&[0xA0, 0x05, 0xD0, 0x15, 0x12, 0x04]).is_empty());
+    }
+
+    #[test]
+    fn skips_check_both_ways() {
+        // 3000 can skip past the loop at 0x202 into the end of the program
+        assert_eq!(lint_rom(&[0x30, 0x00, 0x12, 0x02]), finding(0x204, "execution runs off the end of the program"));
+    }
+}
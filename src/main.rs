@@ -1,16 +1,220 @@
-use std::io::{self, Read};
-use std::fs::File;
+use std::io;
+use std::fs;
 use std::env;
 use std::cell::Cell;
+use std::collections::{BTreeSet, VecDeque};
+use std::time::Duration;
+use std::path::Path;
+use std::error::Error;
+use std::process::ExitCode;
 
+use quirks::{MemoryIncrement, Quirks};
+
+mod audio;
+mod callgraph;
+mod cheader;
+mod cheats;
+mod cli;
+mod config;
+mod crash;
+mod debugger;
+mod disasm;
+mod frames;
 mod gfx;
+mod gzip;
+mod headless;
+mod keypad;
+mod keyrec;
+mod lint;
+mod palette;
+mod quirks;
+mod rng;
+mod romdb;
+mod savestate;
+mod timing;
+mod xbm;
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "serve")]
+mod serve;
 
 const LOAD_ADDR: u16 = 0x200;
 const SCREEN_HEIGHT: u32 = 32;
 const SCREEN_WIDTH: u32 = 64;
+// sprite coordinates are bytes so there's no point going past 256 either way
+const MAX_RESOLUTION: u32 = 256;
+
+const CYCLES_PER_FRAME: u32 = 20;
+
+// what one read or write adds to a byte's --mem-heatmap value
+const HEAT_PER_ACCESS: u16 = 32;
+
+// how deep subroutine calls may nest, the SCHIP limit
+const STACK_LIMIT: usize = 16;
+
+// how many of the last executed instructions the chip remembers, see Chip::history
+const HISTORY_LEN: usize = 32;
+
+// how much to print while running, each -v adds a level
+const LOG_TRACE: u8 = 1; // every instruction
+const LOG_REGISTERS: u8 = 2; // the registers after every instruction
+const LOG_MEMORY: u8 = 3; // memory writes and draws
 
 thread_local! {
-    pub static VERBOSE_OUTPUT: Cell<bool> = Cell::new(false);
+    pub static LOG_LEVEL: Cell<u8> = const { Cell::new(0) };
+}
+
+// frontend settings collected from the command line
+#[derive(Default)]
+pub struct Options {
+    // path of the loaded program
+    pub rom_path: String,
+    // run without a window, see headless.rs
+    pub headless: bool,
+    // stop after this many cycles (headless only)
+    pub max_cycles: Option<u64>,
+    // print the headless summary as json
+    pub json: bool,
+    // write the final display here as an XBM image after a headless run
+    pub export_xbm: Option<String>,
+    // write the machine state here as a C header after a headless run, see cheader.rs
+    pub export_header: Option<String>,
+    // include memory in the header
+    pub export_header_memory: bool,
+    // write a crash report into this directory when an exception halts the program
+    pub crash_dump: Option<String>,
+    // write a save state here after a headless run or when the window closes, see savestate.rs
+    pub save_state: Option<String>,
+    // seconds between save states while running, see savestate::Autosave
+    pub autosave_interval: Option<u64>,
+    // write the subroutine calls seen here on exit, see callgraph.rs
+    pub call_graph: Option<String>,
+    // write every frame (or every nth) as an image into this directory, see frames.rs
+    pub dump_frames: Option<String>,
+    pub dump_frames_every: Option<u64>,
+    // write the random bytes CXNN got here on exit, see rng.rs
+    pub rng_record: Option<String>,
+    // print the sprite at (address, rows) after a headless run
+    pub preview_sprite: Option<(u16, usize)>,
+    // print the display after a headless run
+    pub print_display: bool,
+    // (width, pixels) of a display in --print-display form, cells that differ from it
+    // get highlighted in the window
+    pub diff_overlay: Option<(u32, Box<[u8]>)>,
+    // compare two save states instead of running anything
+    pub diff_state: Option<(String, String)>,
+    // check the loaded program for obvious mistakes and exit, see lint.rs
+    pub lint: bool,
+    // write the loaded program as a csv listing here and exit, see disasm::map
+    pub disasm_map: Option<String>,
+    // size of the loaded program (or the demo)
+    pub rom_len: usize,
+    // print the instruction set and exit
+    pub list_opcodes: bool,
+    // time this many cycles of the program instead of running it normally
+    pub bench: Option<u64>,
+    // run every ROM in batch_roms headless and report opcode coverage
+    pub batch: bool,
+    pub batch_roms: Vec<String>,
+    // target instructions per second, see Options::cpu_hz
+    pub cpu_hz: Option<u32>,
+    // most cycles one frame may run to catch up after a slow one, see gfx::Pacer
+    pub max_catchup_cycles: Option<u32>,
+    // budget frames by estimated VIP machine cycles instead of instruction count
+    pub authentic_timing: bool,
+    // shortest beep in milliseconds, see audio.rs
+    pub min_beep_ms: Option<u64>,
+    // click whenever a sprite collides
+    pub collision_beep: bool,
+    // reload the ROM whenever the file changes
+    pub watch_rom: bool,
+    // run the built-in DEMO_ROM instead of a file
+    pub demo: bool,
+    // draw scanlines over the display
+    pub crt: bool,
+    // draw lines between the cells
+    pub grid: bool,
+    // free space around the display in window pixels
+    pub border: u32,
+    // draw clear pixels lit and set ones dark
+    pub invert: bool,
+    // draw into a texture the size of the chip display and let SDL scale it
+    pub texture: bool,
+    // only draw the cells that changed since the last frame, see gfx::CellCache
+    pub dirty_redraw: bool,
+    // lit pixels are dots instead of filling their cell
+    pub round_pixels: bool,
+    // flicker random pixels in frames that drew something, see gfx::draw_snow
+    pub vip_snow: bool,
+    // display colors, see Options::palette
+    pub theme: Option<palette::Palette>,
+    pub fg: Option<(u8, u8, u8)>,
+    pub bg: Option<(u8, u8, u8)>,
+    // all four XO-CHIP colors, replaces the ones above, see Options::palette4
+    pub xo_palette: Option<palette::Palette4>,
+    // physical keys for the hex keypad, see Options::key_map
+    pub key_map: Option<&'static [&'static str; 16]>,
+    // print which physical key is which chip key when the window opens
+    pub show_keymap: bool,
+    // start in fullscreen mode, F11 toggles it
+    pub fullscreen: bool,
+    // open the window paused, P starts the program
+    pub start_paused: bool,
+    // run DEMO_ROM after this many seconds without a keypress, until the next one
+    pub attract: Option<u64>,
+    // close the window after this many frames
+    pub frames: Option<u64>,
+    // pause once this many instructions have been executed
+    pub run_to_cycle: Option<u64>,
+    // press keys from a recording instead of (as well as) the keyboard, see keyrec.rs
+    pub play: Option<Vec<keyrec::KeyEvent>>,
+    // (address, value) pokes applied every frame, see cheats.rs
+    pub cheats: Vec<(u16, u8)>,
+    // frames a headless FX0A waits before it gets key 0, see Options::key_timeout
+    pub key_timeout: Option<u64>,
+    // write the machine state as json here after a headless run
+    #[cfg(feature = "json")]
+    pub dump_json: Option<String>,
+    // include memory and video memory in the json dump
+    #[cfg(feature = "json")]
+    pub dump_json_memory: bool,
+    // run ROMs sent to this port instead of anything else, see serve.rs
+    #[cfg(feature = "serve")]
+    pub serve: Option<u16>,
+}
+
+impl Options {
+    // the default speed matches the old fixed CYCLES_PER_FRAME at 60 fps
+    pub fn cpu_hz(&self) -> u32 {
+        self.cpu_hz.unwrap_or(CYCLES_PER_FRAME * 60)
+    }
+
+    pub fn key_timeout(&self) -> u64 {
+        self.key_timeout.unwrap_or(headless::DEFAULT_KEY_TIMEOUT)
+    }
+
+    pub fn min_beep(&self) -> Duration {
+        Duration::from_millis(self.min_beep_ms.unwrap_or(audio::DEFAULT_MIN_BEEP_MS))
+    }
+
+    // the theme (or the default one) with --fg and --bg applied on top
+    pub fn palette(&self) -> palette::Palette {
+        let theme = self.theme.unwrap_or(palette::THEMES[0].1);
+        palette::Palette {
+            fg: self.fg.unwrap_or(theme.fg),
+            bg: self.bg.unwrap_or(theme.bg),
+        }
+    }
+
+    // colors by pixel value for drawing, the palette above plus the XO-CHIP plane
+    // colors unless --xo-palette replaces all of them
+    pub fn palette4(&self) -> palette::Palette4 {
+        self.xo_palette.unwrap_or_else(|| self.palette().with_planes())
+    }
+
+    pub fn key_map(&self) -> &'static [&'static str; 16] {
+        self.key_map.unwrap_or(&gfx::KEY_MAP)
+    }
 }
 
 fn u16_from_nibbles_3(n1: u8, n2: u8, n3: u8) -> u16 {
@@ -45,23 +249,66 @@ const FONT_DATA: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80,
 ];
 
+// Built-in program for --demo, a block bouncing around the screen
+const DEMO_ROM: [u8; 58] = [
+    0x60, 0x00, // 0x200: LD V0, 0      x
+    0x61, 0x00, // 0x202: LD V1, 0      y
+    0x62, 0x01, // 0x204: LD V2, 1      dx
+    0x63, 0x01, // 0x206: LD V3, 1      dy
+    0xA2, 0x36, // 0x208: LD I, 0x236   the block sprite
+    0xD0, 0x14, // 0x20A: DRW V0, V1, 4
+    0x64, 0x02, // 0x20C: LD V4, 2      wait two frames
+    0xF4, 0x15, // 0x20E: LD DT, V4
+    0xF4, 0x07, // 0x210: LD V4, DT
+    0x34, 0x00, // 0x212: SE V4, 0
+    0x12, 0x10, // 0x214: JP 0x210
+    0xD0, 0x14, // 0x216: DRW V0, V1, 4 erase it again
+    0x80, 0x24, // 0x218: ADD V0, V2
+    0x81, 0x34, // 0x21A: ADD V1, V3
+    0x30, 0x00, // 0x21C: SE V0, 0      bounce off the left edge
+    0x12, 0x22, // 0x21E: JP 0x222
+    0x62, 0x01, // 0x220: LD V2, 1
+    0x30, 0x3C, // 0x222: SE V0, 60     right edge
+    0x12, 0x28, // 0x224: JP 0x228
+    0x62, 0xFF, // 0x226: LD V2, -1
+    0x31, 0x00, // 0x228: SE V1, 0      top edge
+    0x12, 0x2E, // 0x22A: JP 0x22E
+    0x63, 0x01, // 0x22C: LD V3, 1
+    0x31, 0x1C, // 0x22E: SE V1, 28     bottom edge
+    0x12, 0x34, // 0x230: JP 0x234
+    0x63, 0xFF, // 0x232: LD V3, -1
+    0x12, 0x0A, // 0x234: JP 0x20A
+    0xF0, 0xF0, // 0x236: 4x4 block
+    0xF0, 0xF0,
+];
+
 // Note: this is not part of the original specification
 #[derive(Debug)]
 pub enum ChipException {
-    InvalidRegister,
     ReturnOutsideSubroutine,
+    // more than STACK_LIMIT nested calls
+    StackOverflow,
     IllegalInstruction,
     InvalidFontCodePoint,
-    DrawingOutOfBounds { offset: usize },
+    MemoryOutOfBounds { addr: usize },
     WaitForKey { register: u8 },
     SkipIfPressed { register: u8 },
     SkipIfNotPressed { register: u8 },
+    // FX18 started the sound with --break-on-sound, unlike the ones above the
+    // instruction is already done, the frontend only has to stop
+    SoundStarted { value: u8 },
+    // 00FD, the program is done. not an error
+    Exit,
 }
 
+#[derive(Clone)]
 struct Chip {
     memory: Box<[u8; 4096]>,
     ip: u16,
-    video_memory: Box<[u8; 32*64]>,
+    // screen_width * screen_height pixels, row by row
+    video_memory: Box<[u8]>,
+    screen_width: u32,
+    screen_height: u32,
     stack: Vec<u16>, 
     // registers V0 - VF
     // VF is a little special, being modified by some instructions
@@ -71,6 +318,56 @@ struct Chip {
 
     delay_timer: u8,
     sound_timer: u8,
+    // the register FX0A stores the next key press in while the program waits for one,
+    // see Chip::press_key
+    key_wait: Option<u8>,
+
+    // number of instructions executed so far
+    cycles: u64,
+    // the same in estimated COSMAC VIP machine cycles, see timing.rs
+    machine_cycles: u64,
+    // machine cycles every instruction fetch costs on top of timing::cycle_cost, for --fetch-cost
+    fetch_cost: u32,
+    // the instruction cycle() fetched most recently, for the HUD
+    last_opcode: u16,
+    // (address, opcode) of the last HISTORY_LEN instructions, oldest first
+    history: VecDeque<(u16, u16)>,
+
+    // which bytes of memory have ever been written, only tracked with --strict-memory
+    initialized: Option<Box<[bool; 4096]>>,
+    // how much every byte got read or written lately, for --mem-heatmap. a Cell so
+    // read_mem can stay &self
+    heat: Option<Box<[Cell<u16>; 4096]>>,
+
+    quirks: Quirks,
+
+    // distinct opcode patterns executed so far, only tracked in batch runs
+    coverage: Option<BTreeSet<&'static str>>,
+    // calls between subroutines, only tracked for --call-graph
+    call_graph: Option<callgraph::CallGraph>,
+
+    // print every write into the program area, for --log-writes
+    log_writes: bool,
+    // print the stack on every call and return, for --trace-stack
+    trace_stack: bool,
+    // treat illegal instructions as no-ops, for --skip-illegal
+    skip_illegal: bool,
+    // only skip this many before halting after all, for --illegal-tolerance
+    illegal_tolerance: Option<u64>,
+    skipped_illegal: u64,
+    // where the skipped ones were, to list them when the tolerance runs out
+    skipped_illegal_at: Vec<u16>,
+    // stop whenever FX18 starts the sound, for --break-on-sound
+    break_on_sound: bool,
+    // 0NNN was already complained about
+    machine_code_warned: bool,
+    // random bytes for CXNN, carried across resets so a recording covers the whole run
+    rng: rng::Rng,
+    // larger ROMs don't load, everything from LOAD_ADDR to the end of memory unless
+    // --max-rom-size says less
+    max_rom_size: usize,
+    // addresses of the draws --warn-low-draw already complained about, None if it's off
+    low_draw_warned: Option<BTreeSet<u16>>,
 }
 
 impl Default for Chip {
@@ -81,193 +378,442 @@ impl Default for Chip {
         Self {
             ip: LOAD_ADDR,
             memory,
-            video_memory: Box::new([0; (SCREEN_WIDTH*SCREEN_HEIGHT) as usize]),
+            video_memory: vec![0; (SCREEN_WIDTH*SCREEN_HEIGHT) as usize].into_boxed_slice(),
+            screen_width: SCREEN_WIDTH,
+            screen_height: SCREEN_HEIGHT,
             stack: Vec::new(),
             data_regs: [0; 16],
             addr_reg: 0,
             delay_timer: 0,
             sound_timer: 0,
+            key_wait: None,
+            cycles: 0,
+            machine_cycles: 0,
+            fetch_cost: 0,
+            last_opcode: 0,
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            initialized: None,
+            heat: None,
+            quirks: Quirks::default(),
+            coverage: None,
+            call_graph: None,
+            log_writes: false,
+            trace_stack: false,
+            skip_illegal: false,
+            illegal_tolerance: None,
+            skipped_illegal: 0,
+            skipped_illegal_at: Vec::new(),
+            break_on_sound: false,
+            machine_code_warned: false,
+            max_rom_size: 4096 - LOAD_ADDR as usize,
+            rng: rng::Rng::default(),
+            low_draw_warned: None,
         }
     }
 }
 
 impl Chip {
+    // a freshly powered on machine with the same settings as this one
+    fn fresh(&self) -> Self {
+        let mut chip = Chip {
+            quirks: self.quirks,
+            log_writes: self.log_writes,
+            trace_stack: self.trace_stack,
+            max_rom_size: self.max_rom_size,
+            fetch_cost: self.fetch_cost,
+            rng: self.rng.clone(),
+            skip_illegal: self.skip_illegal,
+            illegal_tolerance: self.illegal_tolerance,
+            break_on_sound: self.break_on_sound,
+            low_draw_warned: self.low_draw_warned.as_ref().map(|_| BTreeSet::new()),
+            call_graph: self.call_graph.as_ref().map(|_| Default::default()),
+            ..Chip::default()
+        };
+        chip.set_resolution(self.screen_width, self.screen_height);
+        if self.initialized.is_some() {
+            chip.enable_strict_memory();
+        }
+        if self.heat.is_some() {
+            chip.enable_heatmap();
+        }
+        chip
+    }
+
+    // some variants use other sizes than 64x32, this also clears the screen
+    fn set_resolution(&mut self, width: u32, height: u32) {
+        self.screen_width = width;
+        self.screen_height = height;
+        self.video_memory = vec![0; (width * height) as usize].into_boxed_slice();
+    }
+
+    // everything that blanks the screen goes through here, anything kept alongside
+    // video_memory for drawing has to be reset here too
+    fn clear_display(&mut self) {
+        self.video_memory.fill(0);
+    }
+
+    fn enable_strict_memory(&mut self) {
+        let mut initialized = Box::new([false; 4096]);
+        initialized[..FONT_DATA.len()].fill(true);
+        self.initialized = Some(initialized);
+    }
+
+    fn enable_heatmap(&mut self) {
+        self.heat = Some(Box::new(std::array::from_fn(|_| Cell::new(0))));
+    }
+
+    fn heat_up(&self, addr: u16) {
+        if let Some(cell) = self.heat.as_ref().and_then(|heat| heat.get(addr as usize)) {
+            cell.set(cell.get().saturating_add(HEAT_PER_ACCESS));
+        }
+    }
+
+    // once a frame, so the heatmap shows what happens now rather than since startup
+    fn cool_down(&self) {
+        for cell in self.heat.iter().flat_map(|heat| heat.iter()) {
+            cell.set(cell.get() - cell.get() / 8);
+        }
+    }
+
+    fn mark_initialized(&mut self, addr: usize, len: usize) {
+        if let Some(initialized) = self.initialized.as_mut() {
+            initialized[addr..addr + len].fill(true);
+        }
+    }
+
+    // warn about reading memory nothing has written to yet, which usually means a
+    // wild jump or a bad I. the byte is marked afterwards so each address only warns once
+    fn check_initialized(&mut self, addr: usize, access: &str) {
+        if let Some(initialized) = self.initialized.as_mut() {
+            if !initialized[addr] {
+                println!("strict memory: {access} of uninitialized memory at 0x{addr:03X} (ip: 0x{:03X})", self.ip);
+                initialized[addr] = true;
+            }
+        }
+    }
+
+    // warn if the loaded program makes a lot more sense with its bytes swapped, a sign the
+    // file was saved with the wrong endianness. fetching always stays big endian
+    fn detect_endian(&self, rom_len: usize) {
+        let rom = &self.memory[LOAD_ADDR as usize..LOAD_ADDR as usize + rom_len];
+        let words = rom_len / 2;
+        let normal = disasm::decodable_words(rom, false);
+        let swapped = disasm::decodable_words(rom, true);
+
+        // code is usually mixed with data, so only complain about a clear difference
+        if swapped > normal + words / 10 {
+            println!("detect-endian: only {normal} of {words} words decode as they are but {swapped} do byte swapped, \
+                      was the ROM saved with the wrong endianness?");
+        } else {
+            println!("detect-endian: {normal} of {words} words decode, byte order looks fine");
+        }
+    }
+
+    // look the loaded program up in the ROM database and say what it is
+    fn identify_rom(&self, rom_len: usize) -> Option<&'static romdb::RomInfo> {
+        let info = romdb::lookup(&self.memory[LOAD_ADDR as usize..LOAD_ADDR as usize + rom_len])?;
+        let hz = info.hz.map(|hz| format!(", {hz} Hz")).unwrap_or_default();
+        println!("Recognized '{}' (recommended: {} quirks{hz}).", info.title, info.quirks);
+        Some(info)
+    }
+
+    // --auto-quirks: the recommended preset of a known ROM, the default one otherwise.
+    // the recommended speed only applies if --hz didn't pick one
+    fn auto_quirks(&mut self, info: Option<&romdb::RomInfo>, rom_len: usize, options: &mut Options) {
+        let name = match info {
+            Some(info) => {
+                if options.cpu_hz.is_none() {
+                    options.cpu_hz = info.hz;
+                }
+                info.quirks
+            }
+            None => {
+                let rom = &self.memory[LOAD_ADDR as usize..LOAD_ADDR as usize + rom_len];
+                println!("auto-quirks: unknown ROM (sha1 {})", romdb::sha1_hex(rom));
+                "default"
+            }
+        };
+        println!("auto-quirks: using the '{name}' preset");
+        self.quirks = quirks::preset(name).unwrap();
+    }
+
+    // preload the display from an XBM image of the same size
+    fn load_splash(&mut self, path: &str) -> Result<(), String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let (width, height, pixels) = xbm::parse(&text)?;
+
+        if (width, height) != (self.screen_width, self.screen_height) {
+            return Err(format!("image is {width}x{height}, the display is {}x{}", self.screen_width, self.screen_height));
+        }
+
+        self.video_memory.copy_from_slice(&pixels);
+        Ok(())
+    }
+
+    // load a program that's already in memory, like the demo
+    fn load_bytes(&mut self, rom: &[u8]) -> usize {
+        let len = rom.len().min(self.memory.len() - LOAD_ADDR as usize);
+        self.memory[LOAD_ADDR as usize..LOAD_ADDR as usize + len].copy_from_slice(&rom[..len]);
+        self.mark_initialized(LOAD_ADDR as usize, len);
+        len
+    }
+
+    // a ROM that doesn't fit is an error rather than cut short. .gz files get unpacked
+    // first. going by the name and not the magic bytes alone, a plain ROM may well start
+    // with 1F8B (jump to 0xF8B)
     fn load_program(&mut self, path: &str) -> io::Result<usize> {
-        let n_read = File::open(path)?
-                        .read(&mut self.memory[(LOAD_ADDR as usize)..])?;
+        let mut rom = fs::read(path)?;
+        if Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gz")) {
+            rom = gzip::decompress(&rom, self.max_rom_size).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        if rom.len() > self.max_rom_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("the ROM is {} bytes, at most {} fit", rom.len(), self.max_rom_size)));
+        }
+        Ok(self.load_bytes(&rom))
+    }
 
-        if n_read > self.memory.len() {
-            println!("ROM might be too large? {} > {}", n_read, self.memory.len()) 
+    // extra data next to the program, for --preload. unlike the ROM it has to fit
+    fn preload(&mut self, addr: u16, path: &str) -> Result<(), String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        let start = addr as usize;
+        if start + data.len() > self.memory.len() {
+            return Err(format!("{} bytes at 0x{addr:03X} go past the end of memory", data.len()));
         }
+        self.memory[start..start + data.len()].copy_from_slice(&data);
+        self.mark_initialized(start, data.len());
+        Ok(())
+    }
 
-        Ok(n_read)
+    fn read_mem(&self, addr: u16) -> Result<u8, ChipException> {
+        self.heat_up(addr);
+        self.memory.get(addr as usize)
+            .copied()
+            .ok_or(ChipException::MemoryOutOfBounds { addr: addr as usize })
     }
 
-    // interpret and execute an instruction
-    fn exec(&mut self, instr: u16) -> Result<(), ChipException> {
-        use ChipException::*;
+    // every store an instruction does goes through here
+    fn write_mem(&mut self, addr: u16, val: u8) -> Result<(), ChipException> {
+        // not read_mem, the heatmap would count the write twice
+        let old = *self.memory.get(addr as usize).ok_or(ChipException::MemoryOutOfBounds { addr: addr as usize })?;
+        self.heat_up(addr);
+        // ip already points past the instruction doing the write
+        if (self.log_writes && addr >= LOAD_ADDR) || LOG_LEVEL.get() >= LOG_MEMORY {
+            println!("write 0x{addr:03X}: 0x{old:02X} -> 0x{val:02X} (ip: 0x{:03X})", self.ip.wrapping_sub(2));
+        }
+        self.memory[addr as usize] = val;
+        self.mark_initialized(addr as usize, 1);
+        Ok(())
+    }
 
-        let nibbles = [((instr & 0xF000) >> 12) as u8, 
-                       ((instr & 0x0F00) >> 8) as u8, 
-                       ((instr & 0x00F0) >> 4) as u8, 
-                       (instr & 0x000F) as u8];
+    // warn about a draw reading its sprite from below the program, once per instruction.
+    // drawing one of the font glyphs is what the font is there for, so that's fine
+    fn check_low_draw(&mut self, n: u8) {
+        let Some(warned) = self.low_draw_warned.as_mut() else {
+            return;
+        };
 
-        if VERBOSE_OUTPUT.get() {
-            println!("[ip: {:X}]: {nibbles:X?}", self.ip);
+        let font_glyph = (self.addr_reg as usize) < FONT_DATA.len() && self.addr_reg.is_multiple_of(5) && n <= 5;
+        let ip = self.ip - 2;
+        if self.addr_reg < LOAD_ADDR && !font_glyph && warned.insert(ip) {
+            println!("low draw: DXYN at 0x{ip:03X} reads its sprite from 0x{:03X}, below the program", self.addr_reg);
         }
+    }
+
+    // make sure len bytes starting at addr_reg are inside memory, done before
+    // multi byte accesses (FX55, DXYN etc.) so they fail without doing half the work.
+    // the error names the first of those bytes that doesn't exist
+    fn check_mem_range(&self, len: usize) -> Result<(), ChipException> {
+        let end = self.addr_reg as usize + len;
+        if end > self.memory.len() {
+            return Err(ChipException::MemoryOutOfBounds { addr: (self.addr_reg as usize).max(self.memory.len()) });
+        }
+        Ok(())
+    }
+
+    // push the return address and jump to the subroutine at addr
+    fn call(&mut self, addr: u16) -> Result<(), ChipException> {
+        if self.stack.len() >= STACK_LIMIT {
+            return Err(ChipException::StackOverflow);
+        }
+        self.stack.push(self.ip);
+        self.ip = addr & 0xFFF;
+        if let Some(graph) = self.call_graph.as_mut() {
+            graph.call(self.ip);
+        }
+        self.print_stack("call");
+        Ok(())
+    }
+
+    // "call 0x2A0, depth 2: [0x206, 0x2B4]", the most recent return address last
+    fn print_stack(&self, what: &str) {
+        if self.trace_stack {
+            let stack: Vec<_> = self.stack.iter().map(|addr| format!("0x{addr:03X}")).collect();
+            println!("{what} 0x{:03X}, depth {}: [{}]", self.ip, self.stack.len(), stack.join(", "));
+        }
+    }
+
+    // the 0x0 group. the specific opcodes overlap with the 0NNN pattern, so they are
+    // matched first in here and 0NNN is always the last thing tried.
+    // new 0x0 opcodes (SCHIP 00CN, 00FB etc.) go above the fallback
+    fn exec_system_opcode(&mut self, nibbles: [u8; 4]) -> Result<(), ChipException> {
+        use ChipException::*;
 
         match nibbles {
             // clear the screen
             [0, 0, 0xE, 0] => {
-                self.video_memory.fill(0);
+                self.clear_display();
             }
             // return from subroutine
             [0, 0, 0xE, 0xE] => {
                 if let Some(addr) = self.stack.pop() {
                     self.ip = addr; 
+                    if let Some(graph) = self.call_graph.as_mut() {
+                        graph.ret();
+                    }
+                    self.print_stack("return to");
                 } else {
                     return Err(ReturnOutsideSubroutine)
                 }
             }
+            // SCHIP: exit the interpreter
+            [0, 0, 0xF, 0xD] => {
+                return Err(Exit);
+            }
             // call (machine language?) subroutine at addr n1n2n3
             // does the same thing as normal call for now
             [0, n1, n2, n3] => {
-                println!("hic sunt dracones: the weird instruction has been encountered. this program might be a bit too 70s");
-                // save return address
-                self.stack.push(self.ip);
-                // jump to subroutine
-                self.ip = u16_from_nibbles_3(n1, n2, n3);
+                // some programs run into this in a loop, once is plenty
+                if LOG_LEVEL.get() >= LOG_TRACE && !self.machine_code_warned {
+                    self.machine_code_warned = true;
+                    println!("hic sunt dracones: the weird instruction has been encountered. this program might be a bit too 70s");
+                }
+                self.call(u16_from_nibbles_3(n1, n2, n3))?;
             }
+            _ => return Err(IllegalInstruction),
+        }
+
+        Ok(())
+    }
+
+    // interpret and execute an instruction
+    fn exec(&mut self, instr: u16) -> Result<(), ChipException> {
+        use ChipException::*;
+
+        // every nibble is masked down to 4 bits here, so register indices taken
+        // from them are always within 0..=0xF and need no further checks
+        let nibbles = [((instr & 0xF000) >> 12) as u8, 
+                       ((instr & 0x0F00) >> 8) as u8, 
+                       ((instr & 0x00F0) >> 4) as u8, 
+                       (instr & 0x000F) as u8];
+
+        if LOG_LEVEL.get() >= LOG_TRACE {
+            let text = disasm::disassemble(instr).unwrap_or_else(|| "???".to_string());
+            println!("[ip: {:X}]: {nibbles:X?}  {text}", self.ip);
+        }
+
+        match nibbles {
+            // 0x0 group, see exec_system_opcode
+            [0, ..] => self.exec_system_opcode(nibbles)?,
             // jmp to n1n2n3
             [1, n1, n2, n3] => {
                 self.ip =  u16_from_nibbles_3(n1, n2, n3);
             }
             // call subroutine at addr n1n2n3
             [2, n1, n2, n3] => {
-                // save return address
-                self.stack.push(self.ip);
-                // jump to subroutine
-                self.ip = u16_from_nibbles_3(n1, n2, n3);
+                self.call(u16_from_nibbles_3(n1, n2, n3))?;
             }
             // skip the next instruction if n1n2 == regs[x]
             [3, x, n1, n2] => {
-                if x > 0xF {
-                    return Err(InvalidRegister)
-                }
                 if self.data_regs[x as usize] == u8_from_nibbles_2(n1, n2) {
                     self.ip += 2; 
                 }
             }
             // skip the next instruction if n1n2 != regs[x]
             [4, x, n1, n2] => {
-                if x > 0xF {
-                    return Err(InvalidRegister)
-                }
                 if self.data_regs[x as usize] != u8_from_nibbles_2(n1, n2) {
                     self.ip += 2; 
                 }
             }
             // skip next instruction if regs[x] == regs[y]
             [5, x, y, 0] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
-
                 if self.data_regs[x as usize] == self.data_regs[y as usize] {
                     self.ip += 2; 
                 }
             }
             // set value of regs[x] to n1n2
             [6, x, n1, n2] => {
-                if x > 0xF {
-                    return Err(InvalidRegister)
-                }
                 self.data_regs[x as usize] = u8_from_nibbles_2(n1, n2);
             }
             // add n1n2 to regs[x]
             [7, x, n1, n2] => {
-                if x > 0xF {
-                    return Err(InvalidRegister)
-                }
                 let value = u8_from_nibbles_2(n1, n2);
                 self.data_regs[x as usize] = self.data_regs[x as usize].wrapping_add(value);
             }
             // set regs[x] = regs[y]
             [8, x, y, 0] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
                 self.data_regs[x as usize] = self.data_regs[y as usize];
             }
             // set regs[x] = regs[x] | regs[y]
             [8, x, y, 1] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
                 self.data_regs[x as usize] |= self.data_regs[y as usize];
+                if self.quirks.vf_reset {
+                    self.data_regs[0xF] = 0;
+                }
             }
             // set regs[x] = regs[x] & regs[y]
             [8, x, y, 2] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
                 self.data_regs[x as usize] &= self.data_regs[y as usize];
+                if self.quirks.vf_reset {
+                    self.data_regs[0xF] = 0;
+                }
             }
             // set regs[x] = regs[x] ^ regs[y]
             [8, x, y, 3] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
                 self.data_regs[x as usize] ^= self.data_regs[y as usize];
+                if self.quirks.vf_reset {
+                    self.data_regs[0xF] = 0;
+                }
             }
-            // add regs[y] to regs[x], set regs[0xF] to 1 if carry, set to 0 if otherwise
+            // add regs[y] to regs[x], set regs[0xF] to 1 if carry, set to 0 if otherwise.
+            // for all of the 8XY_ ops both operands are read first and the flag is written
+            // last, so with x = 0xF the flag wins over the result
             [8, x, y, 4] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
                 let (new_rx, carry) = self.data_regs[x as usize].overflowing_add(self.data_regs[y as usize]);
-                self.data_regs[0xF] = carry as u8;
                 self.data_regs[x as usize] = new_rx;
+                self.data_regs[0xF] = carry as u8;
             }
             // subtract regs[y] from regs[x], set regs[0xF] to 1 if borrow, set to 0 otherwise
             [8, x, y, 5] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
                 let (new_rx, borrow) = self.data_regs[x as usize].overflowing_sub(self.data_regs[y as usize]);
-                self.data_regs[0xF] = borrow as u8;
                 self.data_regs[x as usize] = new_rx;
+                self.data_regs[0xF] = borrow as u8;
             }
             // set regs[x] to regs[y] >> 1, set regs[0xF] to LSb of regs[y] prior to shift
+            // (regs[x] is shifted in place instead with the shift_vx quirk)
             [8, x, y, 6] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
-                self.data_regs[x as usize] = self.data_regs[y as usize] >> 1;
-                self.data_regs[0xF] = self.data_regs[y as usize] & 1;
+                let value = self.data_regs[if self.quirks.shift_vx { x } else { y } as usize];
+                // same order as 8XY4, so 8FF6 leaves the shifted out bit in VF
+                let flag = value & 1;
+                self.data_regs[x as usize] = value >> 1;
+                self.data_regs[0xF] = flag;
             }
             // set regs[x] to regs[y] - regs[x], store if borrow occured in regs[0xF]
             [8, x, y, 7] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
                 let (new_rx, borrow) = self.data_regs[y as usize].overflowing_sub(self.data_regs[x as usize]);
-                self.data_regs[0xF] = borrow as u8;
                 self.data_regs[x as usize] = new_rx;
+                self.data_regs[0xF] = borrow as u8;
             }
             // store regs[y] << 1 in regs[x], set regs[0xF] to MSb prior to shift
+            // (regs[x] is shifted in place instead with the shift_vx quirk)
             [8, x, y, 0xE] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
-                self.data_regs[x as usize] = self.data_regs[y as usize] << 1;
-                self.data_regs[0xF] = self.data_regs[y as usize] >> 7;
+                let value = self.data_regs[if self.quirks.shift_vx { x } else { y } as usize];
+                let flag = value >> 7;
+                self.data_regs[x as usize] = value << 1;
+                self.data_regs[0xF] = flag;
             }
             // skip the next instruction if regs[x] != regs[y]
             [9, x, y, 0] => {
-                if x > 0xF || y > 0xF {
-                    return Err(InvalidRegister)
-                }
                 if self.data_regs[x as usize] != self.data_regs[y as usize] {
                     self.ip += 2;
                 }
@@ -276,108 +822,101 @@ impl Chip {
             [0xA, n1, n2, n3] => {
                 self.addr_reg = u16_from_nibbles_3(n1, n2, n3);
             }
-            // jump to regs[0x0] + n1n2n3
+            // jump to regs[0x0] + n1n2n3 (regs[n1] + n1n2n3 with the jump_vx quirk)
             [0xB, n1, n2, n3] => {
-                self.ip = self.data_regs[0] as u16 + u16_from_nibbles_3(n1, n2, n3);
+                let reg = if self.quirks.jump_vx { n1 } else { 0 };
+                self.ip = self.data_regs[reg as usize] as u16 + u16_from_nibbles_3(n1, n2, n3);
             }
             // Generate a random u8 and apply a n1n2 mask to it 
             [0xC, x, n1, n2] => {
-                if x > 0xF {
-                    return Err(InvalidRegister)
-                }
-                self.data_regs[x as usize] = rand::random::<u8>() & u8_from_nibbles_2(n1, n2);
+                self.data_regs[x as usize] = self.rng.next_byte() & u8_from_nibbles_2(n1, n2);
             }
             // draw sprite at (reg[x],reg[y]) with n bytes of data from memory at addr_register
             // every sprite is eight pixels wide (because 8 bits in a byte)
             [0xD, x, y, n] => {
-                if VERBOSE_OUTPUT.get() {
+                if LOG_LEVEL.get() >= LOG_MEMORY {
                     println!("DRAW CALL: ({},{}), h: {n}", self.data_regs[x as usize], self.data_regs[y as usize]);
                 }
+                // SCHIP: DXY0 draws a 16x16 sprite, two bytes per row, and VF counts
+                // the rows that collided or fell off the bottom instead
+                let large = n == 0 && self.quirks.large_sprites;
+                let (rows, row_bytes) = if large { (16, 2) } else { (n as u16, 1) };
                 let mut set_flag = false;
+                let mut collided_rows = 0;
 
-                let start_row = self.data_regs[y as usize];
-                let start_col = self.data_regs[x as usize];
-                // let start_offset = self.data_regs[y as usize] as u32 * SCREEN_WIDTH + self.data_regs[x as usize] as u32;
-
-                for row in 0..n {
-                    let row_data = self.memory[(self.addr_reg + row as u16) as usize];
-                    for col in 0..8 {
-                        let set = 0 < ((row_data >> (7 - col)) & 1);
-
-                        if set {
-                            // let pixel_offset = (start_offset + (row * 8 + col) as u32) as usize;
-                            let pixel_row = start_row + row;
-                            let pixel_col = start_col + col;
-                            let pixel_offset = (pixel_row as u32 * SCREEN_WIDTH + pixel_col as u32) as usize;
-                            
-                            if pixel_offset > self.video_memory.len() {
-                                return Err(DrawingOutOfBounds { offset: pixel_offset });
-                            } else {
+                // the starting position always wraps, the wrap_x/wrap_y quirks decide
+                // what happens to the pixels that cross an edge
+                let start_row = self.data_regs[y as usize] as u32 % self.screen_height;
+                let start_col = self.data_regs[x as usize] as u32 % self.screen_width;
+                // reset VF up front (after reading the coordinates, x or y might be 0xF)
+                // so a draw interrupted by an exception doesn't leave a stale flag
+                self.data_regs[0xF] = 0;
+
+                self.check_mem_range((rows * row_bytes) as usize)?;
+                self.check_low_draw(n);
+                for row in 0..rows {
+                    let pixel_row = edge(start_row + row as u32, self.screen_height, self.quirks.wrap_y);
+                    let mut row_collided = pixel_row.is_none();
+                    // row_collided also counts clipping, this is only what vf_per_row wants
+                    let mut row_hit = false;
+                    for byte in 0..row_bytes {
+                        let addr = self.addr_reg + row * row_bytes + byte;
+                        self.check_initialized(addr as usize, "sprite read");
+                        let row_data = self.read_mem(addr)?;
+                        for (col, set) in sprite_pixels(row_data).into_iter().enumerate() {
+                            let pixel_col = edge(start_col + (byte * 8) as u32 + col as u32, self.screen_width, self.quirks.wrap_x);
+                            if let (true, Some(pixel_row), Some(pixel_col)) = (set, pixel_row, pixel_col) {
+                                let pixel_offset = (pixel_row * self.screen_width + pixel_col) as usize;
                                 self.video_memory[pixel_offset] ^= 1;
                                 if self.video_memory[pixel_offset] == 0 {
                                     set_flag = true;
+                                    row_collided = true;
+                                    row_hit = true;
                                 }
                             }
                         }
                     }
+                    collided_rows += row_collided as u8;
+                    if self.quirks.vf_per_row {
+                        set_flag = row_hit;
+                    }
                 }
 
-                self.data_regs[0xF] = set_flag as u8;
+                self.data_regs[0xF] = if large { collided_rows } else { set_flag as u8 };
             }
             // skip the next instruction if the key stored in regs[x] is pressed
             [0xE, x, 9, 0xE] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
                 return Err(SkipIfPressed { register: x });
             }
             // skip the next instruction if the key stored in regs[x] is _not_ pressed
             [0xE, x, 0xA, 1] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
                 return Err(SkipIfNotPressed { register: x });
             }
             // store the current value of delay_timer in regs[x]
             [0xF, x, 0, 0x7] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
                 self.data_regs[x as usize] = self.delay_timer;
             }
             // wait for the next keypress and store the result in regs[x]
             [0xF, x, 0, 0xA] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
                 return Err(WaitForKey { register: x });
             }
             // set delay_timer to value of regs[x]
             [0xF, x, 1, 5] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
                 self.delay_timer = self.data_regs[x as usize];
             }
             // set sound_timer to value of regs[x]
             [0xF, x, 1, 8] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
                 self.sound_timer = self.data_regs[x as usize];
+                if self.break_on_sound && self.sound_timer > 0 {
+                    return Err(SoundStarted { value: self.sound_timer });
+                }
             }
             // increment add_reg by regs[x]
             [0xF, x, 1, 0xE] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
                 self.addr_reg = self.addr_reg.wrapping_add(self.data_regs[x as usize] as u16);
             }
             // set addr_reg to point to the font sprite data of value regs[x]
             [0xF, x, 2, 9] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
                 if self.data_regs[x as usize] > 0xF {
                     return Err(InvalidFontCodePoint)
                 }
@@ -385,31 +924,28 @@ impl Chip {
             }
             // store the binary coded decimal of regs[x] at add_reg (offset 0,1,2)
             [0xF, x, 3, 3] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
+                self.check_mem_range(3)?;
                 let (d0, d1, d2) = binary_coded_decimal(self.data_regs[x as usize]);
-                self.memory[self.addr_reg as usize] = d0;
-                self.memory[(self.addr_reg + 1) as usize] = d1;
-                self.memory[(self.addr_reg + 2) as usize] = d2;
+                self.write_mem(self.addr_reg, d0)?;
+                self.write_mem(self.addr_reg + 1, d1)?;
+                self.write_mem(self.addr_reg + 2, d2)?;
             }
             // store the values of regs from regs[0] to regs[x] _inclusive_, at addr_reg
             [0xF, x, 5, 5] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
+                self.check_mem_range(x as usize + 1)?;
                 for i in 0..=x {
-                    self.memory[(self.addr_reg + i as u16) as usize] = self.data_regs[i as usize];
+                    self.write_mem(self.addr_reg + i as u16, self.data_regs[i as usize])?;
                 }
+                self.increment_after_memory_access(x);
             }
             // fill regs from regs[0] to regs[x] _inclusive_, from memory starting at addr_reg
             [0xF, x, 6, 5] => {
-                if x > 0xF {
-                    return Err(InvalidRegister);
-                }
+                self.check_mem_range(x as usize + 1)?;
                 for i in 0..=x {
-                    self.data_regs[i as usize] = self.memory[(self.addr_reg + i as u16) as usize];
+                    self.check_initialized((self.addr_reg + i as u16) as usize, "load");
+                    self.data_regs[i as usize] = self.read_mem(self.addr_reg + i as u16)?;
                 }
+                self.increment_after_memory_access(x);
             }
             _ => return Err(IllegalInstruction),
         };
@@ -419,63 +955,1270 @@ impl Chip {
 
     fn cycle(&mut self) -> Result<(), ChipException> {
         // fetch next instruction
-        let next = u16::from_be_bytes([self.memory[self.ip as usize], self.memory[(self.ip + 1) as usize]]);
+        let next = u16::from_be_bytes([self.read_mem(self.ip)?, self.read_mem(self.ip + 1)?]);
+        self.check_initialized(self.ip as usize, "instruction fetch");
+        self.check_initialized(self.ip as usize + 1, "instruction fetch");
         self.ip += 2; // increment instruction pointer, this might get overriden by a jmp
-        self.exec(next)
+        self.last_opcode = next;
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back((self.ip - 2, next));
+        self.cycles += 1;
+        self.machine_cycles += (timing::cycle_cost(next) + self.fetch_cost) as u64;
+        if let Some(coverage) = self.coverage.as_mut() {
+            if let Some(info) = disasm::decode(next) {
+                coverage.insert(info.pattern);
+            }
+        }
+
+        let result = match self.exec(next) {
+            // ip already points at the following instruction, so just carry on
+            Err(ChipException::IllegalInstruction) if self.illegal_tolerance.is_some_and(|n| self.skipped_illegal >= n) => {
+                let skipped: Vec<_> = self.skipped_illegal_at.iter().map(|addr| format!("0x{addr:03X}")).collect();
+                println!("too many illegal instructions, halting. skipped before: [{}]", skipped.join(", "));
+                Err(ChipException::IllegalInstruction)
+            }
+            Err(ChipException::IllegalInstruction) if self.skip_illegal => {
+                self.skipped_illegal += 1;
+                self.skipped_illegal_at.push(self.ip - 2);
+                println!("skipped illegal instruction {next:04X} at 0x{:03X} ({} so far)", self.ip - 2, self.skipped_illegal);
+                Ok(())
+            }
+            result => result,
+        };
+
+        if LOG_LEVEL.get() >= LOG_REGISTERS {
+            println!("    {:02X?} I: {:X} DT: {} ST: {}", self.data_regs, self.addr_reg, self.delay_timer, self.sound_timer);
+        }
+        result
+    }
+
+    // ends an FX0A wait. the frontend stops calling cycle() while key_wait is set but
+    // keeps drawing and ticking the timers, like the VIP does
+    fn press_key(&mut self, key: u8) {
+        if let Some(register) = self.key_wait.take() {
+            self.data_regs[register as usize] = key;
+        }
+    }
+
+    // I after FX55/FX65 with registers up to x, depending on the memory_increment quirk
+    fn increment_after_memory_access(&mut self, x: u8) {
+        self.addr_reg += match self.quirks.memory_increment {
+            MemoryIncrement::Unchanged => 0,
+            MemoryIncrement::IncrementByX => x as u16,
+            MemoryIncrement::IncrementByXPlus1 => x as u16 + 1,
+        };
+    }
+
+    // one instruction like cycle(), handing back the opcode it ran. the exceptions
+    // are the same, last_opcode still has the one that raised them
+    fn step(&mut self) -> Result<u16, ChipException> {
+        self.cycle().map(|()| self.last_opcode)
+    }
+
+    // called at 60Hz
+    fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    // the display as text, one line per row with '#' for lit pixels
+    fn framebuffer_ascii(&self) -> String {
+        let mut out = String::new();
+        for row in self.video_memory.chunks(self.screen_width as usize) {
+            out.extend(row.iter().map(|&pixel| if pixel == 1 { '#' } else { '.' }));
+            out.push('\n');
+        }
+        out
+    }
+
+    // the other way around. lines with anything but '#' and '.' are skipped, so the
+    // whole output of a headless run with --print-display can be fed back in
+    fn parse_framebuffer_ascii(text: &str) -> Option<(u32, Box<[u8]>)> {
+        let lines: Vec<_> = text.lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty() && line.chars().all(|c| c == '#' || c == '.'))
+            .collect();
+        let width = lines.first()?.len();
+        if lines.iter().any(|line| line.len() != width) {
+            return None;
+        }
+        let pixels = lines.concat().chars().map(|c| (c == '#') as u8).collect();
+        Some((width as u32, pixels))
+    }
+
+    // FNV-1a over the framebuffer, cheap fingerprint for comparing runs. the
+    // resolution goes in first so a blank hires screen doesn't hash like a blank lores one
+    fn framebuffer_hash(&self) -> u64 {
+        let size = self.screen_width.to_be_bytes().into_iter().chain(self.screen_height.to_be_bytes());
+        size.chain(self.video_memory.iter().copied()).fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+}
+
+fn die_usage(path: &str) -> ! {
+    eprintln!("{}", cli::usage(path));
+    std::process::exit(EXIT_ERROR as i32);
+}
+
+// parse the value following an option, bail out with usage if it's malformed
+fn parse_value<T: std::str::FromStr>(path: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| die_usage(path))
+}
+
+// a sprite pixel's row or column past the edge of the display either wraps around
+// or is clipped (None)
+fn edge(pos: u32, size: u32, wrap: bool) -> Option<u32> {
+    if pos < size {
+        Some(pos)
+    } else if wrap {
+        Some(pos % size)
+    } else {
+        None
+    }
+}
+
+// the pixels of one sprite row, leftmost first
+fn sprite_pixels(row: u8) -> [bool; 8] {
+    std::array::from_fn(|col| (row >> (7 - col)) & 1 == 1)
+}
+
+// "123" or "0x7B"
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+// "V3=0x1A" -> (3, 0x1A)
+fn parse_register_assignment(s: &str) -> Option<(u8, u8)> {
+    let (reg, value) = s.split_once('=')?;
+    let reg = reg.strip_prefix(['V', 'v'])?;
+    if reg.len() != 1 {
+        return None;
+    }
+    let reg = u8::from_str_radix(reg, 16).ok()?;
+    let value = u8::try_from(parse_number(value)?).ok()?;
+    Some((reg, value))
+}
+
+// "0x300:data.bin" -> (0x300, "data.bin")
+fn parse_preload(s: &str) -> Option<(u16, &str)> {
+    let (addr, file) = s.split_once(':')?;
+    let addr = parse_number(addr).filter(|&n| n <= 0xFFF)?;
+    (!file.is_empty()).then_some((addr as u16, file))
+}
+
+// "64x32" -> (64, 32)
+fn parse_resolution(s: &str) -> Option<(u32, u32)> {
+    let (width, height) = s.split_once('x')?;
+    let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+    let valid = 1..=MAX_RESOLUTION;
+    (valid.contains(&width) && valid.contains(&height)).then_some((width, height))
+}
+
+fn die(msg: &str) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(EXIT_ERROR as i32);
+}
+
+// "--opt=value" -> "--opt", "value"
+fn split_inline_value(arg: String) -> Vec<String> {
+    match arg.split_once('=') {
+        Some((opt, value)) if arg.starts_with("--") => vec![opt.to_string(), value.to_string()],
+        _ => vec![arg],
     }
 }
 
-fn die_usage(path: &String) -> ! {
-    eprintln!("\
-usage: ./{path} [OPTIONS..] [PATH]
-Options:
-    --help          Show this message
-    --verbose | -v  Verbose mode");
-    std::process::exit(1);
+// the arguments that are neither options nor their values, walked the same way
+// handle_args does so a ROM before or between options is found too
+fn rom_args(args: &[String]) -> Vec<&String> {
+    let mut roms = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match cli::find(arg) {
+            Some(info) => {
+                for _ in info.args {
+                    iter.next();
+                }
+            }
+            None if !arg.starts_with('-') => roms.push(arg),
+            None => {}
+        }
+    }
+    roms
+}
+
+// the settings file for the ROM given last on the command line, if there is one
+fn config_args(args: &[String]) -> Vec<String> {
+    if args.iter().any(|arg| ["--no-config", "--batch", "--diff-state"].contains(&arg.as_str())) {
+        return Vec::new();
+    }
+
+    let Some(config) = rom_args(args).last().and_then(|rom| config::find(rom)) else {
+        return Vec::new();
+    };
+
+    match config::load(&config) {
+        Ok(args) => {
+            println!("Using settings from '{}'.", config.display());
+            args.into_iter().flat_map(split_inline_value).collect()
+        }
+        Err(e) => die(&format!("Couldn't read '{}' - {e}", config.display())),
+    }
 }
 
-fn handle_args(chip: &mut Chip) {
-    let args: Vec<_> = env::args().collect();
-    let path = args.first().unwrap();
+fn handle_args(chip: &mut Chip) -> Result<Options, Box<dyn Error>> {
+    let mut args: Vec<_> = env::args().flat_map(split_inline_value).collect();
+    let path = &args[0].clone();
 
     if args.len() == 1 {
         die_usage(path);
     }
 
-    // handle intermediate options
-    for arg in args.iter()
-        .skip(1)
-        .take(args.len() - 2) 
-    {
-        match arg.as_str() {
-            "--verbose" | "-v" => {
-                VERBOSE_OUTPUT.set(true);
-                println!("Verbose mode set.");
-            }
-            _ => {
+    // settings from the ROM's config file go first so the command line can override them
+    let config = config_args(&args[1..]);
+    args.splice(1..1, config);
+
+    let mut options = Options::default();
+    let mut roms = Vec::new();
+    let mut register_values = Vec::new();
+    let mut addr_reg_value = None;
+    let mut preloads = Vec::new();
+    // (wrap_x, wrap_y), applied on top of whatever preset ends up being used
+    let mut wrap = None;
+    let mut vf_per_row = false;
+    let mut detect_endian = false;
+    let mut auto_quirks = false;
+    let mut splash = None;
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        // anything that isn't an option should be the path of a binary
+        let Some(info) = cli::find(arg) else {
+            if arg.starts_with('-') {
+                eprintln!("Unknown option '{arg}'");
                 die_usage(path);
-            } 
+            }
+            roms.push(arg.clone());
+            continue;
+        };
+        let values: Vec<&String> = info.args.iter().map(|_| iter.next().unwrap_or_else(|| die_usage(path))).collect();
+
+        match info.name {
+            "--help" => die_usage(path),
+            "--verbose" => {
+                let levels = if arg.starts_with("--") { 1 } else { arg.len() as u8 - 1 };
+                LOG_LEVEL.set(LOG_LEVEL.get().saturating_add(levels));
+                println!("Verbose mode set (level {}).", LOG_LEVEL.get());
+            }
+            "--headless" => {
+                options.headless = true;
+            }
+            "--max-cycles" => {
+                options.max_cycles = Some(parse_value(path, values[0]));
+            }
+            "--json" => {
+                options.json = true;
+            }
+            "--hz" => {
+                let hz: u32 = parse_value(path, values[0]);
+                if hz == 0 {
+                    die_usage(path);
+                }
+                options.cpu_hz = Some(hz);
+            }
+            "--authentic-timing" => {
+                options.authentic_timing = true;
+            }
+            "--max-catchup-cycles" => {
+                options.max_catchup_cycles = Some(parse_value(path, values[0]));
+            }
+            "--fetch-cost" => {
+                chip.fetch_cost = parse_value(path, values[0]);
+            }
+            "--min-beep-ms" => {
+                options.min_beep_ms = Some(parse_value(path, values[0]));
+            }
+            "--collision-beep" => {
+                options.collision_beep = true;
+            }
+            "--watch-rom" => {
+                options.watch_rom = true;
+            }
+            "--demo" => {
+                options.demo = true;
+            }
+            "--crt" => {
+                options.crt = true;
+            }
+            "--dirty-redraw" => {
+                options.dirty_redraw = true;
+            }
+            "--pixel-shape" => {
+                options.round_pixels = match values[0].as_str() {
+                    "square" => false,
+                    "circle" => true,
+                    _ => die("--pixel-shape expects square or circle"),
+                };
+            }
+            "--vip-snow" => {
+                options.vip_snow = true;
+            }
+            "--grid" => {
+                options.grid = true;
+            }
+            "--border" => {
+                options.border = parse_value(path, values[0]);
+            }
+            "--invert" => {
+                options.invert = true;
+            }
+            "--texture" => {
+                options.texture = true;
+            }
+            "--theme" => {
+                let name = values[0];
+                options.theme = Some(palette::theme(name).unwrap_or_else(|| {
+                    die(&format!("Unknown theme '{name}', available: {}", palette::theme_names().join(", ")))
+                }));
+            }
+            "--xo-palette" => {
+                let value = values[0];
+                options.xo_palette = Some(palette::parse_palette4(value).unwrap_or_else(|| {
+                    die(&format!("Invalid palette '{value}', expected four RRGGBB colors separated by commas"))
+                }));
+            }
+            "--fg" | "--bg" => {
+                let value = values[0];
+                let color = palette::parse_color(value).unwrap_or_else(|| {
+                    die(&format!("Invalid color '{value}', expected RRGGBB hex"))
+                });
+                if arg == "--fg" {
+                    options.fg = Some(color);
+                } else {
+                    options.bg = Some(color);
+                }
+            }
+            "--keypad" => {
+                let name = values[0];
+                let key_map = gfx::keypad(name).unwrap_or_else(|| {
+                    let names: Vec<_> = gfx::KEYPADS.iter().map(|(name, _)| *name).collect();
+                    die(&format!("Unknown keypad layout '{name}', available: {}", names.join(", ")))
+                });
+                let conflicts = gfx::frontend_key_conflicts(key_map);
+                if !conflicts.is_empty() {
+                    die(&format!("Keypad layout '{name}' uses keys the window needs itself: {}", conflicts.join(", ")));
+                }
+                options.key_map = Some(key_map);
+            }
+            "--show-keymap" => {
+                options.show_keymap = true;
+            }
+            "--fullscreen" => {
+                options.fullscreen = true;
+            }
+            "--start-paused" => {
+                options.start_paused = true;
+            }
+            "--attract" => {
+                options.attract = Some(parse_value(path, values[0]));
+            }
+            "--frames" => {
+                options.frames = Some(parse_value(path, values[0]));
+            }
+            "--run-to-cycle" => {
+                options.run_to_cycle = Some(parse_value(path, values[0]));
+            }
+            "--cheats" => {
+                let file = values[0];
+                options.cheats = cheats::load(file).unwrap_or_else(|e| die(&format!("Couldn't load '{file}' - {e}")));
+            }
+            "--key-timeout" => {
+                options.key_timeout = Some(parse_value(path, values[0]));
+            }
+            "--play" => {
+                let file = values[0];
+                options.play = Some(keyrec::load(file).unwrap_or_else(|e| die(&format!("Couldn't load '{file}' - {e}"))));
+            }
+            "--quirks" => {
+                let name = values[0];
+                chip.quirks = quirks::preset(name).unwrap_or_else(|| {
+                    die(&format!("Unknown quirks preset '{name}', available: {}", quirks::preset_names().join(", ")))
+                });
+            }
+            "--wrap" => {
+                wrap = Some(match values[0].as_str() {
+                    "none" => (false, false),
+                    "x" => (true, false),
+                    "y" => (false, true),
+                    "xy" => (true, true),
+                    _ => die("--wrap expects none, x, y or xy"),
+                });
+            }
+            "--vf-per-row" => {
+                vf_per_row = true;
+            }
+            "--detect-endian" => {
+                detect_endian = true;
+            }
+            "--auto-quirks" => {
+                auto_quirks = true;
+            }
+            "--resolution" => {
+                let value = values[0];
+                let (width, height) = parse_resolution(value).unwrap_or_else(|| {
+                    die(&format!("Invalid resolution '{value}', expected WxH with both between 1 and 256"))
+                });
+                chip.set_resolution(width, height);
+            }
+            "--strict-memory" => {
+                chip.enable_strict_memory();
+            }
+            "--mem-heatmap" => {
+                chip.enable_heatmap();
+            }
+            "--log-writes" => {
+                chip.log_writes = true;
+            }
+            "--trace-stack" => {
+                chip.trace_stack = true;
+            }
+            "--warn-low-draw" => {
+                chip.low_draw_warned = Some(BTreeSet::new());
+            }
+            "--break-on-sound" => {
+                chip.break_on_sound = true;
+            }
+            "--max-rom-size" => {
+                let max = parse_value(path, values[0]);
+                if max > chip.max_rom_size {
+                    die(&format!("--max-rom-size can be at most {}, everything from 0x{LOAD_ADDR:03X} on", chip.max_rom_size));
+                }
+                chip.max_rom_size = max;
+            }
+            "--skip-illegal" => {
+                chip.skip_illegal = true;
+            }
+            "--illegal-tolerance" => {
+                chip.skip_illegal = true;
+                chip.illegal_tolerance = Some(parse_value(path, values[0]));
+            }
+            "--set-reg" => {
+                let value = values[0];
+                register_values.push(parse_register_assignment(value).unwrap_or_else(|| {
+                    die(&format!("Invalid register assignment '{value}', expected Vx=n with x in 0-F and n in 0-255"))
+                }));
+            }
+            "--preload" => {
+                let value = values[0];
+                preloads.push(parse_preload(value).unwrap_or_else(|| {
+                    die(&format!("Invalid preload '{value}', expected addr:file with addr in 0-0xFFF"))
+                }));
+            }
+            "--set-i" => {
+                let value = values[0];
+                addr_reg_value = Some(parse_number(value).filter(|&n| n <= 0xFFF).unwrap_or_else(|| {
+                    die(&format!("Invalid address register value '{value}', expected 0-0xFFF"))
+                }) as u16);
+            }
+            #[cfg(feature = "json")]
+            "--dump-json" => {
+                options.dump_json = Some(parse_value(path, values[0]));
+            }
+            #[cfg(feature = "json")]
+            "--dump-json-memory" => {
+                options.dump_json_memory = true;
+            }
+            #[cfg(feature = "serve")]
+            "--serve" => {
+                options.serve = Some(parse_value(path, values[0]));
+            }
+            "--export-header" => {
+                options.export_header = Some(values[0].clone());
+            }
+            "--export-header-memory" => {
+                options.export_header_memory = true;
+            }
+            "--export-xbm" => {
+                options.export_xbm = Some(parse_value(path, values[0]));
+            }
+            "--preview-sprite" => {
+                let addr = parse_number(values[0]).filter(|&n| n <= 0xFFF);
+                let rows = parse_number(values[1]);
+                let (Some(addr), Some(rows)) = (addr, rows) else { die_usage(path) };
+                options.preview_sprite = Some((addr as u16, rows as usize));
+            }
+            "--print-display" => {
+                options.print_display = true;
+            }
+            "--diff-overlay" => {
+                let file = values[0];
+                let text = fs::read_to_string(file).unwrap_or_else(|e| die(&format!("Couldn't read '{file}' - {e}")));
+                options.diff_overlay = Some(Chip::parse_framebuffer_ascii(&text).unwrap_or_else(|| {
+                    die(&format!("'{file}' isn't a display dump, expected rows of '#' and '.' of the same length"))
+                }));
+            }
+            "--bench" => {
+                options.bench = Some(parse_value(path, values[0]));
+            }
+            "--lint" => {
+                options.lint = true;
+            }
+            "--disasm-map" => {
+                options.disasm_map = Some(parse_value(path, values[0]));
+            }
+            "--list-opcodes" => {
+                options.list_opcodes = true;
+            }
+            "--crash-dump" => {
+                options.crash_dump = Some(parse_value(path, values[0]));
+            }
+            "--autosave-interval" => {
+                let secs: u64 = parse_value(path, values[0]);
+                if secs == 0 {
+                    die_usage(path);
+                }
+                options.autosave_interval = Some(secs);
+            }
+            "--dump-frames" => {
+                options.dump_frames = Some(values[0].clone());
+            }
+            "--dump-frames-every" => {
+                let every: u64 = parse_value(path, values[0]);
+                if every == 0 {
+                    die_usage(path);
+                }
+                options.dump_frames_every = Some(every);
+            }
+            "--call-graph" => {
+                chip.call_graph = Some(Default::default());
+                options.call_graph = Some(values[0].clone());
+            }
+            "--rng-record" => {
+                chip.rng.record();
+                options.rng_record = Some(values[0].clone());
+            }
+            "--rng-replay" => {
+                let file = values[0];
+                chip.rng.replay(file).unwrap_or_else(|e| die(&format!("Couldn't load '{file}' - {e}")));
+            }
+            "--save-state" => {
+                options.save_state = Some(parse_value(path, values[0]));
+            }
+            "--diff-state" => {
+                options.diff_state = Some((values[0].clone(), values[1].clone()));
+            }
+            "--splash-xbm" => {
+                splash = Some(parse_value::<String>(path, values[0]));
+            }
+            "--batch" => {
+                options.batch = true;
+            }
+            // handled by config_args
+            "--no-config" => {}
+            name => match info.feature {
+                Some(feature) => die(&format!("{name} requires the '{feature}' feature, this build doesn't have it")),
+                None => unreachable!("{name} is in cli::OPTIONS but not handled"),
+            },
         }
     }
 
-    // last arg should be the path of the binary
-    if let Some(arg) = args.last() {
-        match chip.load_program(arg) {
-            Ok(n) => {
-                println!("Loaded {n} Bytes from file '{arg}'.");
-            },
-            Err(e) => {
-                eprintln!("Couldn't load '{arg}' - {e}");
-                std::process::exit(1);
-            }
+    // nothing to load, the states are read in try_main
+    if options.diff_state.is_some() || options.list_opcodes {
+        return Ok(options);
+    }
+    // the ROMs come in over the network
+    #[cfg(feature = "serve")]
+    if options.serve.is_some() {
+        return Ok(options);
+    }
+
+    let override_quirks = |quirks: &mut Quirks| {
+        if let Some((x, y)) = wrap {
+            quirks.wrap_x = x;
+            quirks.wrap_y = y;
+        }
+        quirks.vf_per_row |= vf_per_row;
+    };
+    override_quirks(&mut chip.quirks);
+
+    // batch runs load their ROMs one after another later on
+    if options.batch {
+        if roms.is_empty() || options.max_cycles.is_none() {
+            die("--batch needs --max-cycles and at least one ROM");
+        }
+        options.batch_roms = roms;
+        return Ok(options);
+    }
+
+    if options.demo && roms.is_empty() {
+        let n = chip.load_bytes(&DEMO_ROM);
+        println!("Loaded {n} Bytes of the built-in demo.");
+        options.rom_len = n;
+        let info = chip.identify_rom(n);
+        if auto_quirks {
+            chip.auto_quirks(info, n, &mut options);
+        }
+    } else if let [arg] = roms.as_slice() {
+        options.rom_path = arg.clone();
+        let n = chip.load_program(arg).map_err(|e| format!("Couldn't load '{arg}' - {e}"))?;
+        println!("Loaded {n} Bytes from file '{arg}'.");
+        options.rom_len = n;
+        let info = chip.identify_rom(n);
+        if auto_quirks {
+            chip.auto_quirks(info, n, &mut options);
+        }
+        if detect_endian {
+            chip.detect_endian(n);
         }
     } else {
         die_usage(path);
     }
+    // again, --auto-quirks might have replaced them
+    override_quirks(&mut chip.quirks);
+
+    for (addr, file) in preloads {
+        chip.preload(addr, file).map_err(|e| format!("Couldn't preload '{file}' - {e}"))?;
+    }
+    for (reg, value) in register_values {
+        chip.data_regs[reg as usize] = value;
+    }
+    if let Some(value) = addr_reg_value {
+        chip.addr_reg = value;
+    }
+    if let Some(splash) = splash {
+        chip.load_splash(&splash).map_err(|e| format!("Couldn't load '{splash}' - {e}"))?;
+    }
+
+    Ok(options)
 }
 
-fn main() {
+// exit codes, so scripts can tell how a run went:
+// 0 normal exit, 1 usage or load error, 2 runtime exception (headless only,
+// the window just freezes so the screen can be inspected)
+pub const EXIT_ERROR: u8 = 1;
+pub const EXIT_EXCEPTION: u8 = 2;
+
+fn try_main() -> Result<ExitCode, Box<dyn Error>> {
     let mut chip = Chip::default();
-    handle_args(&mut chip);
-    gfx::spawn_window(chip);
+    let options = handle_args(&mut chip)?;
+
+    #[cfg(feature = "serve")]
+    if let Some(port) = options.serve {
+        serve::serve(&chip, &options, port)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if options.list_opcodes {
+        for line in disasm::opcode_table() {
+            println!("{line}");
+        }
+        Ok(ExitCode::SUCCESS)
+    } else if let Some((a, b)) = &options.diff_state {
+        savestate::diff_files(a, b)?;
+        Ok(ExitCode::SUCCESS)
+    } else if options.lint {
+        lint::print(&chip.memory[..], options.rom_len);
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(map) = &options.disasm_map {
+        let lines = disasm::map(&chip.memory[..], LOAD_ADDR, options.rom_len);
+        fs::write(map, lines.join("\n") + "\n").map_err(|e| format!("Couldn't write '{map}' - {e}"))?;
+        println!("wrote {} instructions to '{map}'", lines.len() - 1);
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(cycles) = options.bench {
+        Ok(headless::run_bench(&chip, &options, cycles))
+    } else if options.batch {
+        Ok(headless::run_batch(&chip, &options))
+    } else if options.headless {
+        Ok(headless::run(chip, &options))
+    } else {
+        gfx::spawn_window(chip, &options);
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn main() -> ExitCode {
+    try_main().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        ExitCode::from(EXIT_ERROR)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh chip with rom at LOAD_ADDR, the tests of the other modules use these too
+    pub fn chip_with(rom: &[u8]) -> Chip {
+        let mut chip = Chip::default();
+        chip.load_bytes(rom);
+        chip
+    }
+
+    // run n instructions, none of them may fail
+    pub fn run(chip: &mut Chip, n: usize) {
+        for _ in 0..n {
+            chip.step().unwrap();
+        }
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // a file in the temp directory that goes away again after the test
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, data: &[u8]) -> Self {
+            let path = env::temp_dir().join(format!("chip8-test-{}-{name}", std::process::id()));
+            fs::write(&path, data).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn plain_rom_starting_like_gzip_loads_as_is() {
+        let rom = TempFile::new("jump.ch8", &[0x1F, 0x8B, 0x08, 0x00, 0x12, 0x00]);
+        let mut chip = Chip::default();
+        assert_eq!(chip.load_program(rom.path()).unwrap(), 6);
+        assert_eq!(&chip.memory[0x200..0x206], [0x1F, 0x8B, 0x08, 0x00, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn gz_rom_gets_unpacked() {
+        // gzip.compress(b"\x00\xe0\x12\x00", mtime=0) with a stored block
+        let gz = [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03, 0x01, 0x04, 0x00, 0xfb, 0xff,
+                  0x00, 0xe0, 0x12, 0x00, 0x6f, 0x7f, 0x52, 0xf0, 0x04, 0x00, 0x00, 0x00];
+        let rom = TempFile::new("rom.ch8.gz", &gz);
+        let mut chip = Chip::default();
+        assert_eq!(chip.load_program(rom.path()).unwrap(), 4);
+        assert_eq!(&chip.memory[0x200..0x204], [0x00, 0xE0, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn framebuffer_ascii_of_a_cleared_display() {
+        let mut chip = Chip::default();
+        chip.set_resolution(4, 2);
+        assert_eq!(chip.framebuffer_ascii(), "....\n....\n");
+    }
+
+    #[test]
+    fn framebuffer_ascii_shows_a_sprite() {
+        // the font's 0 at (1, 0): A000 points I at it, D015 draws it
+        let mut chip = chip_with(&[0xA0, 0x00, 0x60, 0x01, 0x61, 0x00, 0xD0, 0x15]);
+        chip.set_resolution(8, 5);
+        run(&mut chip, 4);
+        assert_eq!(chip.framebuffer_ascii(), "\
+.####...
+.#..#...
+.#..#...
+.#..#...
+.####...
+");
+    }
+
+    #[test]
+    fn framebuffer_ascii_parses_back() {
+        let mut chip = Chip::default();
+        chip.set_resolution(4, 2);
+        chip.video_memory.copy_from_slice(&[1, 0, 0, 1, 0, 1, 1, 0]);
+        let text = chip.framebuffer_ascii();
+        assert_eq!(text, "#..#\n.##.\n");
+        let (width, pixels) = Chip::parse_framebuffer_ascii(&format!("cycles=10\n{text}")).unwrap();
+        assert_eq!(width, 4);
+        assert_eq!(pixels, chip.video_memory);
+    }
+
+    #[test]
+    fn rom_args_skip_option_values() {
+        let args = strings(&["pong.ch8", "--headless", "--max-cycles", "1"]);
+        assert_eq!(rom_args(&args), ["pong.ch8"]);
+        let args = strings(&["--headless", "--max-cycles", "1", "pong.ch8"]);
+        assert_eq!(rom_args(&args), ["pong.ch8"]);
+        let args = strings(&["--preview-sprite", "0x200", "5", "pong.ch8", "-vv"]);
+        assert_eq!(rom_args(&args), ["pong.ch8"]);
+    }
+
+    #[test]
+    fn every_instruction_keeps_its_registers_in_range() {
+        // the register nibbles can't be above 0xF, so nothing indexes past V0-VF.
+        // every opcode either runs or fails with an exception, none of them panics
+        for instr in 0..=0xFFFFu16 {
+            let mut chip = chip_with(&instr.to_be_bytes());
+            chip.addr_reg = 0x300;
+            let _ = chip.step();
+        }
+    }
+
+    #[test]
+    fn register_f_works_like_the_others() {
+        // 6FAB, 7F01, 8EF0
+        let mut chip = chip_with(&[0x6F, 0xAB, 0x7F, 0x01, 0x8E, 0xF0]);
+        run(&mut chip, 3);
+        assert_eq!(chip.data_regs[0xF], 0xAC);
+        assert_eq!(chip.data_regs[0xE], 0xAC);
+    }
+
+    #[test]
+    fn memory_past_the_end_is_an_error() {
+        // AFFF, F165 loads two bytes from 0xFFF
+        let mut chip = chip_with(&[0xAF, 0xFF, 0xF1, 0x65]);
+        chip.step().unwrap();
+        assert!(matches!(chip.step(), Err(ChipException::MemoryOutOfBounds { addr: 0x1000 })));
+    }
+
+    #[test]
+    fn draw_sets_vf_only_on_collision() {
+        // A000 (font 0), D015 twice: the first draw lights pixels, the second erases them
+        let mut chip = chip_with(&[0xA0, 0x00, 0xD0, 0x15, 0xD0, 0x15]);
+        chip.data_regs[0xF] = 1;
+        run(&mut chip, 2);
+        assert_eq!(chip.data_regs[0xF], 0);
+        run(&mut chip, 1);
+        assert_eq!(chip.data_regs[0xF], 1);
+    }
+
+    #[test]
+    fn interrupted_draw_leaves_vf_at_0() {
+        // AFFF, D015 reads past the end of memory
+        let mut chip = chip_with(&[0xAF, 0xFF, 0xD0, 0x15]);
+        chip.data_regs[0xF] = 1;
+        chip.step().unwrap();
+        assert!(chip.step().is_err());
+        assert_eq!(chip.data_regs[0xF], 0);
+    }
+
+    #[test]
+    fn clear_screen_is_not_a_call() {
+        let mut chip = chip_with(&[0x00, 0xE0]);
+        chip.video_memory.fill(1);
+        assert_eq!(chip.step().unwrap(), 0x00E0);
+        assert!(chip.video_memory.iter().all(|&pixel| pixel == 0));
+        assert!(chip.stack.is_empty());
+    }
+
+    #[test]
+    fn return_is_not_a_call() {
+        // 2206 calls 0x206, which returns right away
+        let mut chip = chip_with(&[0x22, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0xEE]);
+        run(&mut chip, 2);
+        assert_eq!(chip.ip, 0x202);
+        assert!(chip.stack.is_empty());
+
+        let mut chip = chip_with(&[0x00, 0xEE]);
+        assert!(matches!(chip.step(), Err(ChipException::ReturnOutsideSubroutine)));
+    }
+
+    #[test]
+    fn other_0nnn_calls() {
+        let mut chip = chip_with(&[0x03, 0x40]);
+        run(&mut chip, 1);
+        assert_eq!(chip.ip, 0x340);
+        assert_eq!(chip.stack, [0x202]);
+    }
+
+    #[test]
+    fn read_and_write_stop_at_the_last_byte() {
+        let mut chip = Chip::default();
+        chip.write_mem(0xFFF, 0x42).unwrap();
+        assert_eq!(chip.read_mem(0xFFF).unwrap(), 0x42);
+        assert!(matches!(chip.read_mem(0x1000), Err(ChipException::MemoryOutOfBounds { addr: 0x1000 })));
+        assert!(matches!(chip.write_mem(0x1000, 1), Err(ChipException::MemoryOutOfBounds { addr: 0x1000 })));
+    }
+
+    #[test]
+    fn stores_up_to_the_end_of_memory() {
+        // FX55 and FX33 with I right at the end: all bytes fit, one more doesn't
+        let mut chip = chip_with(&[0xF7, 0x55, 0xF8, 0x55]);
+        chip.addr_reg = 0xFF8;
+        chip.data_regs[7] = 0x77;
+        chip.step().unwrap();
+        assert_eq!(chip.memory[0xFFF], 0x77);
+        assert!(matches!(chip.step(), Err(ChipException::MemoryOutOfBounds { addr: 0x1000 })));
+
+        let mut chip = chip_with(&[0xF0, 0x33, 0xF0, 0x33]);
+        chip.data_regs[0] = 123;
+        chip.addr_reg = 0xFFD;
+        chip.step().unwrap();
+        assert_eq!(&chip.memory[0xFFD..], [1, 2, 3]);
+        chip.addr_reg = 0xFFE;
+        assert!(chip.step().is_err());
+        // nothing written by the failed one
+        assert_eq!(&chip.memory[0xFFD..], [1, 2, 3]);
+    }
+
+    #[test]
+    fn fetch_past_the_end_is_an_error() {
+        let mut chip = Chip { ip: 0xFFE, ..Chip::default() };
+        chip.memory[0xFFE..].copy_from_slice(&[0x60, 0x01]);
+        chip.step().unwrap();
+        assert!(matches!(chip.step(), Err(ChipException::MemoryOutOfBounds { addr: 0x1000 })));
+    }
+
+    // one instruction with some registers set up first
+    fn exec_with(instr: u16, regs: &[(usize, u8)]) -> Chip {
+        let mut chip = chip_with(&instr.to_be_bytes());
+        for &(reg, value) in regs {
+            chip.data_regs[reg] = value;
+        }
+        run(&mut chip, 1);
+        chip
+    }
+
+    #[test]
+    fn add_with_vf_as_operand() {
+        // 81F4: VF is only read, the carry replaces it afterwards
+        let chip = exec_with(0x81F4, &[(1, 0xFF), (0xF, 0x01)]);
+        assert_eq!((chip.data_regs[1], chip.data_regs[0xF]), (0x00, 1));
+        // 8F14: VF is the destination, the flag wins over the sum
+        assert_eq!(exec_with(0x8F14, &[(0xF, 0xFF), (1, 0x01)]).data_regs[0xF], 1);
+        assert_eq!(exec_with(0x8F14, &[(0xF, 0x10), (1, 0x01)]).data_regs[0xF], 0);
+    }
+
+    #[test]
+    fn subtract_with_vf_as_operand() {
+        let chip = exec_with(0x81F5, &[(1, 0x05), (0xF, 0x10)]);
+        assert_eq!((chip.data_regs[1], chip.data_regs[0xF]), (0xF5, 1));
+        assert_eq!(exec_with(0x8F15, &[(0xF, 0x10), (1, 0x01)]).data_regs[0xF], 0);
+        assert_eq!(exec_with(0x8F15, &[(0xF, 0x01), (1, 0x10)]).data_regs[0xF], 1);
+
+        let chip = exec_with(0x81F7, &[(1, 0x01), (0xF, 0x10)]);
+        assert_eq!((chip.data_regs[1], chip.data_regs[0xF]), (0x0F, 0));
+        assert_eq!(exec_with(0x8F17, &[(0xF, 0x10), (1, 0x01)]).data_regs[0xF], 1);
+    }
+
+    #[test]
+    fn shift_with_vf_as_operand() {
+        // 81F6 shifts VF into V1
+        let chip = exec_with(0x81F6, &[(0xF, 0x03)]);
+        assert_eq!((chip.data_regs[1], chip.data_regs[0xF]), (0x01, 1));
+        let chip = exec_with(0x81FE, &[(0xF, 0x40)]);
+        assert_eq!((chip.data_regs[1], chip.data_regs[0xF]), (0x80, 0));
+    }
+
+    #[test]
+    fn exit_stops_without_an_error() {
+        // 00FD, then 6001 that must not run
+        let mut chip = chip_with(&[0x00, 0xFD, 0x60, 0x01]);
+        assert!(matches!(chip.step(), Err(ChipException::Exit)));
+        assert_eq!(chip.last_opcode, 0x00FD);
+        assert_eq!(chip.data_regs[0], 0);
+        assert_eq!(chip.cycles, 1);
+    }
+
+    #[test]
+    fn deep_recursion_overflows_the_stack() {
+        // 2200 calls itself forever
+        let mut chip = chip_with(&[0x22, 0x00]);
+        run(&mut chip, STACK_LIMIT);
+        assert_eq!(chip.stack.len(), STACK_LIMIT);
+        assert!(matches!(chip.step(), Err(ChipException::StackOverflow)));
+        assert_eq!(chip.stack.len(), STACK_LIMIT);
+
+        // 0NNN goes through the same check
+        let mut chip = chip_with(&[0x02, 0x00]);
+        run(&mut chip, STACK_LIMIT);
+        assert!(matches!(chip.step(), Err(ChipException::StackOverflow)));
+    }
+
+    #[test]
+    fn call_masks_the_target_to_12_bits() {
+        let mut chip = Chip::default();
+        chip.call(0x1234).unwrap();
+        assert_eq!(chip.ip, 0x234);
+        assert_eq!(chip.stack, [LOAD_ADDR]);
+    }
+
+    #[test]
+    fn shifting_vf_into_vf_keeps_the_flag() {
+        // 8FF6: 0x02 >> 1 would be 1, the bit shifted out is 0
+        assert_eq!(exec_with(0x8FF6, &[(0xF, 0x02)]).data_regs[0xF], 0);
+        assert_eq!(exec_with(0x8FF6, &[(0xF, 0x03)]).data_regs[0xF], 1);
+        // 8FFE: 0x81 << 1 would be 2, the bit shifted out is 1
+        assert_eq!(exec_with(0x8FFE, &[(0xF, 0x81)]).data_regs[0xF], 1);
+        assert_eq!(exec_with(0x8FFE, &[(0xF, 0x7F)]).data_regs[0xF], 0);
+    }
+
+    #[test]
+    fn shift_flag_with_shift_vx() {
+        // shifting V1 in place, VY is ignored
+        let mut chip = chip_with(&[0x81, 0x26, 0x83, 0x4E]);
+        chip.quirks.shift_vx = true;
+        chip.data_regs[1] = 0x05;
+        chip.data_regs[3] = 0x81;
+        run(&mut chip, 2);
+        assert_eq!(chip.data_regs[1], 0x02);
+        assert_eq!(chip.data_regs[3], 0x02);
+        assert_eq!(chip.data_regs[0xF], 1);
+    }
+
+    #[test]
+    fn framebuffer_hash_changes_with_a_draw() {
+        // A000, D015 draws the font's 0, 6001 doesn't touch the display
+        let mut chip = chip_with(&[0xA0, 0x00, 0x60, 0x01, 0xD0, 0x15]);
+        let blank = chip.framebuffer_hash();
+        run(&mut chip, 2);
+        assert_eq!(chip.framebuffer_hash(), blank);
+        run(&mut chip, 1);
+        let drawn = chip.framebuffer_hash();
+        assert_ne!(drawn, blank);
+        assert_eq!(chip.framebuffer_hash(), drawn);
+    }
+
+    #[test]
+    fn framebuffer_hash_includes_the_resolution() {
+        // 128x64 and 64x128 have as many pixels as each other, all of them dark
+        let mut hires = Chip::default();
+        hires.set_resolution(128, 64);
+        let mut tall = Chip::default();
+        tall.set_resolution(64, 128);
+        assert_ne!(hires.framebuffer_hash(), tall.framebuffer_hash());
+        assert_ne!(hires.framebuffer_hash(), Chip::default().framebuffer_hash());
+    }
+
+    // I after FX55 and after FX65, both with registers up to V3 and I at 0x300
+    fn i_after_memory_access(increment: MemoryIncrement) -> (u16, u16) {
+        let mut i = [0; 2];
+        for (n, instr) in [0xF355u16, 0xF365].into_iter().enumerate() {
+            let mut chip = chip_with(&instr.to_be_bytes());
+            chip.quirks.memory_increment = increment;
+            chip.addr_reg = 0x300;
+            run(&mut chip, 1);
+            i[n] = chip.addr_reg;
+        }
+        (i[0], i[1])
+    }
+
+    #[test]
+    fn memory_increment_unchanged() {
+        assert_eq!(i_after_memory_access(MemoryIncrement::Unchanged), (0x300, 0x300));
+    }
+
+    #[test]
+    fn memory_increment_by_x() {
+        assert_eq!(i_after_memory_access(MemoryIncrement::IncrementByX), (0x303, 0x303));
+    }
+
+    #[test]
+    fn memory_increment_by_x_plus_1() {
+        assert_eq!(i_after_memory_access(MemoryIncrement::IncrementByXPlus1), (0x304, 0x304));
+    }
+
+    #[test]
+    fn clear_display_zeroes_the_framebuffer() {
+        let mut chip = Chip::default();
+        chip.set_resolution(128, 64);
+        chip.video_memory.fill(3);
+        chip.clear_display();
+        assert!(chip.video_memory.iter().all(|&pixel| pixel == 0));
+        // the resolution stays
+        assert_eq!(chip.video_memory.len(), 128 * 64);
+    }
+
+    // an 8x2 block drawn at (60, 31), so it crosses the right and the bottom edge
+    fn draw_across_the_corner(wrap_x: bool, wrap_y: bool) -> Chip {
+        let mut chip = chip_with(&[0x60, 60, 0x61, 31, 0xA3, 0x00, 0xD0, 0x12]);
+        chip.memory[0x300..0x302].fill(0xFF);
+        chip.quirks.wrap_x = wrap_x;
+        chip.quirks.wrap_y = wrap_y;
+        run(&mut chip, 4);
+        chip
+    }
+
+    fn lit(chip: &Chip, col: usize, row: usize) -> bool {
+        chip.video_memory[row * chip.screen_width as usize + col] == 1
+    }
+
+    // (inside, wrapped right, wrapped down, wrapped both ways) lit
+    fn corner_pixels(chip: &Chip) -> (bool, bool, bool, bool) {
+        (lit(chip, 63, 31), lit(chip, 0, 31), lit(chip, 63, 0), lit(chip, 0, 0))
+    }
+
+    #[test]
+    fn sprite_clipped_on_both_axes() {
+        let chip = draw_across_the_corner(false, false);
+        assert_eq!(corner_pixels(&chip), (true, false, false, false));
+        assert_eq!(chip.video_memory.iter().filter(|&&pixel| pixel == 1).count(), 4);
+    }
+
+    #[test]
+    fn sprite_wrapped_on_x_only() {
+        let chip = draw_across_the_corner(true, false);
+        assert_eq!(corner_pixels(&chip), (true, true, false, false));
+        assert_eq!(chip.video_memory.iter().filter(|&&pixel| pixel == 1).count(), 8);
+    }
+
+    #[test]
+    fn sprite_wrapped_on_y_only() {
+        let chip = draw_across_the_corner(false, true);
+        assert_eq!(corner_pixels(&chip), (true, false, true, false));
+        assert_eq!(chip.video_memory.iter().filter(|&&pixel| pixel == 1).count(), 8);
+    }
+
+    #[test]
+    fn sprite_wrapped_on_both_axes() {
+        let chip = draw_across_the_corner(true, true);
+        assert_eq!(corner_pixels(&chip), (true, true, true, true));
+        assert_eq!(chip.video_memory.iter().filter(|&&pixel| pixel == 1).count(), 16);
+    }
+
+    // the SCHIP preset with a solid 16x16 sprite at 0x300
+    fn solid_large_sprite(chip: &mut Chip) {
+        chip.quirks = quirks::preset("schip").unwrap();
+        chip.memory[0x300..0x320].fill(0xFF);
+    }
+
+    #[test]
+    fn large_sprite_counts_collided_rows() {
+        // A300, D013 lights rows 0-2, D010 draws the 16x16 sprite over them
+        let mut chip = chip_with(&[0xA3, 0x00, 0xD0, 0x13, 0xD0, 0x10]);
+        solid_large_sprite(&mut chip);
+        run(&mut chip, 3);
+        assert_eq!(chip.data_regs[0xF], 3);
+        // the three rows got erased, the rest is lit
+        assert!(!lit(&chip, 0, 2) && lit(&chip, 8, 2) && lit(&chip, 0, 3));
+    }
+
+    #[test]
+    fn large_sprite_counts_clipped_rows() {
+        // at y = 24 the last 8 of the 16 rows fall off the bottom
+        let mut chip = chip_with(&[0x61, 24, 0xA3, 0x00, 0xD0, 0x10]);
+        solid_large_sprite(&mut chip);
+        run(&mut chip, 3);
+        assert_eq!(chip.data_regs[0xF], 8);
+    }
+
+    #[test]
+    fn narrow_sprite_keeps_a_boolean_vf() {
+        let mut chip = chip_with(&[0xA3, 0x00, 0xD0, 0x13, 0xD0, 0x13]);
+        solid_large_sprite(&mut chip);
+        run(&mut chip, 3);
+        assert_eq!(chip.data_regs[0xF], 1);
+    }
+
+    #[test]
+    fn tall_sprite_at_the_top_of_memory_fails_cleanly() {
+        // AFF8, D01F: 15 rows from 0xFF8 need memory up to 0x1006
+        let mut chip = chip_with(&[0xAF, 0xF8, 0xD0, 0x1F]);
+        chip.data_regs[0xF] = 1;
+        chip.step().unwrap();
+        assert!(matches!(chip.step(), Err(ChipException::MemoryOutOfBounds { addr: 0x1000 })));
+        // nothing half drawn, no stale flag
+        assert!(chip.video_memory.iter().all(|&pixel| pixel == 0));
+        assert_eq!(chip.data_regs[0xF], 0);
+    }
+
+    #[test]
+    fn oversized_rom_is_refused() {
+        let rom = TempFile::new("big.ch8", &[0x12; 3585]);
+        let mut chip = Chip::default();
+        let e = chip.load_program(rom.path()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "the ROM is 3585 bytes, at most 3584 fit");
+        // nothing loaded
+        assert_eq!(chip.memory[LOAD_ADDR as usize], 0);
+    }
+
+    #[test]
+    fn rom_filling_all_of_memory_loads() {
+        let rom = TempFile::new("full.ch8", &[0x12; 3584]);
+        let mut chip = Chip::default();
+        assert_eq!(chip.load_program(rom.path()).unwrap(), 3584);
+        assert_eq!(chip.memory[0xFFF], 0x12);
+    }
+
+    #[test]
+    fn max_rom_size_lowers_the_limit() {
+        let rom = TempFile::new("small.ch8", &[0x12; 200]);
+        let mut chip = Chip { max_rom_size: 100, ..Chip::default() };
+        assert_eq!(chip.load_program(rom.path()).unwrap_err().to_string(), "the ROM is 200 bytes, at most 100 fit");
+    }
+
+    // the first row of the second sprite collides, the second row doesn't
+    fn draw_over_the_first_row(vf_per_row: bool) -> Chip {
+        let mut chip = chip_with(&[0xA3, 0x00, 0xD0, 0x11, 0xA3, 0x01, 0xD0, 0x12]);
+        chip.quirks.vf_per_row = vf_per_row;
+        chip.memory[0x300..0x303].fill(0xFF);
+        run(&mut chip, 4);
+        chip
+    }
+
+    #[test]
+    fn vf_per_row_only_keeps_the_last_row() {
+        assert_eq!(draw_over_the_first_row(true).data_regs[0xF], 0);
+    }
+
+    #[test]
+    fn without_vf_per_row_any_row_sets_vf() {
+        assert_eq!(draw_over_the_first_row(false).data_regs[0xF], 1);
+    }
+
+    #[test]
+    fn step_hands_back_the_opcode() {
+        let mut chip = chip_with(&[0x60, 0x12, 0x70, 0x01, 0xA3, 0x00]);
+        assert_eq!(chip.step().unwrap(), 0x6012);
+        assert_eq!(chip.step().unwrap(), 0x7001);
+        assert_eq!(chip.step().unwrap(), 0xA300);
+        assert_eq!(chip.data_regs[0], 0x13);
+    }
+
+    #[test]
+    fn step_passes_exceptions_through() {
+        let mut chip = chip_with(&[0xF3, 0x0A]);
+        assert!(matches!(chip.step(), Err(ChipException::WaitForKey { register: 3 })));
+        assert_eq!(chip.last_opcode, 0xF30A);
+    }
+
+    #[test]
+    fn auto_quirks_fall_back_to_the_default_preset() {
+        let mut chip = chip_with(&[0x12, 0x00]);
+        chip.quirks = quirks::preset("schip").unwrap();
+        let mut options = Options::default();
+        chip.auto_quirks(None, 2, &mut options);
+        assert_eq!(chip.quirks, quirks::preset("default").unwrap());
+        assert_eq!(options.cpu_hz, None);
+    }
+
+    #[test]
+    fn auto_quirks_use_the_recommendation() {
+        let info = romdb::RomInfo { sha1: "", title: "test", quirks: "schip", hz: Some(1000) };
+        let mut chip = chip_with(&[0x12, 0x00]);
+        let mut options = Options::default();
+        chip.auto_quirks(Some(&info), 2, &mut options);
+        assert_eq!(chip.quirks, quirks::preset("schip").unwrap());
+        assert_eq!(options.cpu_hz, Some(1000));
+
+        // --hz wins over the recommended speed
+        options.cpu_hz = Some(600);
+        chip.auto_quirks(Some(&info), 2, &mut options);
+        assert_eq!(options.cpu_hz, Some(600));
+    }
+
+    #[test]
+    fn splash_of_the_wrong_size_is_refused() {
+        let small = TempFile::new("small.xbm", xbm::emit(&[0; 32 * 16], 32, 16).as_bytes());
+        let mut chip = Chip::default();
+        assert_eq!(chip.load_splash(small.path()), Err("image is 32x16, the display is 64x32".to_string()));
+
+        let mut pixels = vec![0; 64 * 32];
+        pixels[65] = 1;
+        let image = TempFile::new("splash.xbm", xbm::emit(&pixels, 64, 32).as_bytes());
+        chip.load_splash(image.path()).unwrap();
+        assert_eq!(chip.video_memory[..], pixels[..]);
+    }
 }
@@ -0,0 +1,57 @@
+// display colors. a theme picks both, --fg and --bg override single ones
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    // lit pixels
+    pub fg: (u8, u8, u8),
+    pub bg: (u8, u8, u8),
+}
+
+// XO-CHIP colors, indexed by the pixel value: background, plane 0, plane 1 and both
+// planes. with a single plane only the first two ever show up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette4 {
+    pub colors: [(u8, u8, u8); 4],
+}
+
+// plane 1 and both planes unless --xo-palette says otherwise, the same as Octo
+const PLANE_COLORS: [(u8, u8, u8); 2] = [(255, 102, 0), (102, 34, 0)];
+
+impl Palette {
+    pub fn with_planes(self) -> Palette4 {
+        Palette4 { colors: [self.bg, self.fg, PLANE_COLORS[0], PLANE_COLORS[1]] }
+    }
+}
+
+pub const THEMES: [(&str, Palette); 5] = [
+    ("default", Palette { fg: (255, 255, 255), bg: (18, 18, 18) }),
+    ("gameboy", Palette { fg: (15, 56, 15), bg: (155, 188, 15) }),
+    ("amber", Palette { fg: (255, 176, 0), bg: (40, 20, 0) }),
+    ("ibm", Palette { fg: (255, 255, 255), bg: (0, 0, 170) }),
+    ("matrix", Palette { fg: (0, 255, 65), bg: (13, 2, 8) }),
+];
+
+pub fn theme(name: &str) -> Option<Palette> {
+    THEMES.iter()
+        .find(|(theme, _)| *theme == name)
+        .map(|(_, palette)| *palette)
+}
+
+pub fn theme_names() -> Vec<&'static str> {
+    THEMES.iter().map(|(name, _)| *name).collect()
+}
+
+// "000000,FFCC00,FF6600,662200"
+pub fn parse_palette4(s: &str) -> Option<Palette4> {
+    let colors: Vec<_> = s.split(',').map(|color| parse_color(color.trim())).collect::<Option<_>>()?;
+    Some(Palette4 { colors: colors.try_into().ok()? })
+}
+
+// "#FFB000" or "FFB000"
+pub fn parse_color(s: &str) -> Option<(u8, u8, u8)> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
@@ -0,0 +1,99 @@
+// behaviours that differ between CHIP-8 interpreters. ROMs are usually written
+// against one of them, so the default keeps what this emulator always did
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place instead of storing VY shifted into VX
+    pub shift_vx: bool,
+    // where FX55/FX65 leave I, see MemoryIncrement
+    pub memory_increment: MemoryIncrement,
+    // BNNN jumps to NNN + VX (X being the highest nibble of NNN) instead of NNN + V0
+    pub jump_vx: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    // sprites crossing the right/bottom edge of the display continue on the other side
+    // instead of being clipped, one setting per axis
+    pub wrap_x: bool,
+    pub wrap_y: bool,
+    // DXY0 draws a 16x16 sprite and VF is the number of rows that collided (SCHIP)
+    pub large_sprites: bool,
+    // DXYN sets VF after every row instead of once for the whole sprite, so it ends up
+    // telling whether the last row collided. only some reference emulators do this
+    pub vf_per_row: bool,
+}
+
+// what FX55/FX65 do to I after accessing V0 to VX
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum MemoryIncrement {
+    // I stays where it was (SCHIP 1.1)
+    #[default]
+    Unchanged,
+    // I points at the last byte accessed (SCHIP 1.0)
+    IncrementByX,
+    // I points past the last byte accessed (COSMAC VIP, XO-CHIP)
+    IncrementByXPlus1,
+}
+
+pub const PRESETS: [(&str, Quirks); 5] = [
+    ("default", Quirks {
+        shift_vx: false,
+        memory_increment: MemoryIncrement::Unchanged,
+        jump_vx: false,
+        vf_reset: false,
+        wrap_x: false,
+        wrap_y: false,
+        large_sprites: false,
+        vf_per_row: false,
+    }),
+    // the original COSMAC VIP interpreter
+    ("chip8", Quirks {
+        shift_vx: false,
+        memory_increment: MemoryIncrement::IncrementByXPlus1,
+        jump_vx: false,
+        vf_reset: true,
+        wrap_x: false,
+        wrap_y: false,
+        large_sprites: false,
+        vf_per_row: false,
+    }),
+    ("schip", Quirks {
+        shift_vx: true,
+        memory_increment: MemoryIncrement::Unchanged,
+        jump_vx: true,
+        vf_reset: false,
+        wrap_x: false,
+        wrap_y: false,
+        large_sprites: true,
+        vf_per_row: false,
+    }),
+    // the first SCHIP release, 1.1 stopped touching I
+    ("schip1.0", Quirks {
+        shift_vx: true,
+        memory_increment: MemoryIncrement::IncrementByX,
+        jump_vx: true,
+        vf_reset: false,
+        wrap_x: false,
+        wrap_y: false,
+        large_sprites: true,
+        vf_per_row: false,
+    }),
+    ("xochip", Quirks {
+        shift_vx: false,
+        memory_increment: MemoryIncrement::IncrementByXPlus1,
+        jump_vx: false,
+        vf_reset: false,
+        wrap_x: true,
+        wrap_y: true,
+        large_sprites: false,
+        vf_per_row: false,
+    }),
+];
+
+pub fn preset(name: &str) -> Option<Quirks> {
+    PRESETS.iter()
+        .find(|(preset, _)| *preset == name)
+        .map(|(_, quirks)| *quirks)
+}
+
+pub fn preset_names() -> Vec<&'static str> {
+    PRESETS.iter().map(|(name, _)| *name).collect()
+}
@@ -0,0 +1,41 @@
+// where CXNN gets its random bytes. normally straight from rand, but the bytes can be
+// recorded (--rng-record) and fed back in the same order (--rng-replay), so a replay
+// sees the same numbers however the frontend's timing changes when they get asked for.
+// the file is just the bytes, one per CXNN
+use std::collections::VecDeque;
+use std::fs;
+
+#[derive(Clone, Default)]
+pub struct Rng {
+    // handed out first, rand takes over once they run out
+    replay: VecDeque<u8>,
+    // everything handed out so far, None unless recording
+    recorded: Option<Vec<u8>>,
+}
+
+impl Rng {
+    pub fn next_byte(&mut self) -> u8 {
+        let byte = self.replay.pop_front().unwrap_or_else(rand::random);
+        if let Some(recorded) = self.recorded.as_mut() {
+            recorded.push(byte);
+        }
+        byte
+    }
+
+    pub fn replay(&mut self, path: &str) -> Result<(), String> {
+        self.replay = fs::read(path).map_err(|e| e.to_string())?.into();
+        Ok(())
+    }
+
+    pub fn record(&mut self) {
+        self.recorded = Some(Vec::new());
+    }
+
+    pub fn save(&self, path: &str) {
+        let bytes = self.recorded.as_deref().unwrap_or_default();
+        match fs::write(path, bytes) {
+            Ok(()) => println!("saved {} random bytes to '{path}'", bytes.len()),
+            Err(e) => eprintln!("Couldn't write '{path}' - {e}"),
+        }
+    }
+}
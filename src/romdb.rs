@@ -0,0 +1,114 @@
+// known ROMs, so loading one can say what it is and what it wants. the table is
+// compiled in, there is no database file to ship
+pub struct RomInfo {
+    // of the whole file
+    pub sha1: &'static str,
+    pub title: &'static str,
+    // name of a quirks preset, see quirks.rs
+    pub quirks: &'static str,
+    // recommended instructions per second, None if the default is fine
+    pub hz: Option<u32>,
+}
+
+// only add entries whose hash was taken from the actual file, loading an unknown
+// ROM with --auto-quirks prints its hash to make that easy
+const ROMS: &[RomInfo] = &[
+    RomInfo {
+        sha1: "a33b2183e9cc40102c4b07624c6e56ce61413759",
+        title: "Bouncing block (the built-in --demo)",
+        quirks: "default",
+        hz: None,
+    },
+];
+
+pub fn lookup(rom: &[u8]) -> Option<&'static RomInfo> {
+    let hash = sha1_hex(rom);
+    ROMS.iter().find(|info| info.sha1 == hash)
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1(data).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    // pad to a multiple of 64 bytes, ending with the message length in bits
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for chunk in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(chunk.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0; 20];
+    for (bytes, h) in out.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha1_matches_the_standard_vectors() {
+        assert_eq!(sha1_hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // 56 bytes, the padding spills into a second block
+        assert_eq!(sha1_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+                   "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+
+    #[test]
+    fn the_demo_is_known() {
+        assert_eq!(lookup(&crate::DEMO_ROM).unwrap().quirks, "default");
+    }
+
+    #[test]
+    fn every_entry_names_a_preset() {
+        for info in ROMS {
+            assert!(crate::quirks::preset(info.quirks).is_some(), "{}", info.title);
+        }
+    }
+
+    #[test]
+    fn unknown_roms_arent_found() {
+        assert!(lookup(&[0x12, 0x00]).is_none());
+    }
+}
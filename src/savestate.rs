@@ -0,0 +1,331 @@
+// machine snapshots for --save-state and --diff-state. a small binary format, all
+// numbers big endian:
+//
+//     "C8ST" version
+//     ip, I, V0-VF, DT, ST, cycles, machine cycles, quirks (one bit each, memory
+//     increment by X gets its own bit next to the x + 1 one, then wrap x and y and large sprites)
+//     more quirks (vf per row), not in version 1 states
+//     stack depth, stack entries
+//     width, height, one byte per pixel
+//     4096 bytes of memory
+//
+// only the machine is saved, frontend settings like --strict-memory are not
+
+use crate::{Chip, Options, Quirks, MAX_RESOLUTION, STACK_LIMIT};
+use crate::quirks::MemoryIncrement;
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 2;
+
+impl Chip {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+
+        out.extend_from_slice(&self.ip.to_be_bytes());
+        out.extend_from_slice(&self.addr_reg.to_be_bytes());
+        out.extend_from_slice(&self.data_regs);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend_from_slice(&self.cycles.to_be_bytes());
+        out.extend_from_slice(&self.machine_cycles.to_be_bytes());
+        let q = self.quirks;
+        let by_x_plus_1 = q.memory_increment == MemoryIncrement::IncrementByXPlus1;
+        let by_x = q.memory_increment == MemoryIncrement::IncrementByX;
+        out.push(q.shift_vx as u8 | (by_x_plus_1 as u8) << 1 | (q.jump_vx as u8) << 2 | (q.vf_reset as u8) << 3 | (by_x as u8) << 4
+                 | (q.wrap_x as u8) << 5 | (q.wrap_y as u8) << 6 | (q.large_sprites as u8) << 7);
+        out.push(q.vf_per_row as u8);
+
+        out.push(self.stack.len() as u8);
+        for addr in &self.stack {
+            out.extend_from_slice(&addr.to_be_bytes());
+        }
+
+        out.extend_from_slice(&(self.screen_width as u16).to_be_bytes());
+        out.extend_from_slice(&(self.screen_height as u16).to_be_bytes());
+        out.extend_from_slice(&self.video_memory);
+        out.extend_from_slice(&self.memory[..]);
+        out
+    }
+
+    pub fn load_state(data: &[u8]) -> Result<Chip, String> {
+        let mut reader = Reader { data };
+        if reader.take(4)? != MAGIC {
+            return Err("not a save state".to_string());
+        }
+        let version = reader.u8()?;
+        if version != VERSION && version != 1 {
+            return Err(format!("unsupported save state version {version}"));
+        }
+
+        let mut chip = Chip {
+            ip: reader.u16()?,
+            addr_reg: reader.u16()?,
+            data_regs: reader.take(16)?.try_into().unwrap(),
+            delay_timer: reader.u8()?,
+            sound_timer: reader.u8()?,
+            cycles: u64::from_be_bytes(reader.take(8)?.try_into().unwrap()),
+            machine_cycles: u64::from_be_bytes(reader.take(8)?.try_into().unwrap()),
+            ..Chip::default()
+        };
+        let q = reader.u8()?;
+        chip.quirks = Quirks {
+            shift_vx: q & 1 != 0,
+            memory_increment: match (q & 2 != 0, q & 16 != 0) {
+                (true, _) => MemoryIncrement::IncrementByXPlus1,
+                (false, true) => MemoryIncrement::IncrementByX,
+                (false, false) => MemoryIncrement::Unchanged,
+            },
+            jump_vx: q & 4 != 0,
+            vf_reset: q & 8 != 0,
+            wrap_x: q & 32 != 0,
+            wrap_y: q & 64 != 0,
+            large_sprites: q & 128 != 0,
+            vf_per_row: version > 1 && reader.u8()? & 1 != 0,
+        };
+
+        let depth = reader.u8()? as usize;
+        if depth > STACK_LIMIT {
+            return Err(format!("stack depth {depth} is more than {STACK_LIMIT}"));
+        }
+        for _ in 0..depth {
+            chip.stack.push(reader.u16()?);
+        }
+
+        let width = reader.u16()? as u32;
+        let height = reader.u16()? as u32;
+        let valid = 1..=MAX_RESOLUTION;
+        if !valid.contains(&width) || !valid.contains(&height) {
+            return Err(format!("bad resolution {width}x{height}"));
+        }
+        let pixels = reader.take((width * height) as usize)?;
+        chip.set_resolution(width, height);
+        chip.video_memory.copy_from_slice(pixels);
+        chip.memory.copy_from_slice(reader.take(4096)?);
+
+        if !reader.data.is_empty() {
+            return Err("trailing data after the save state".to_string());
+        }
+        Ok(chip)
+    }
+
+    pub fn read_state_file(path: &str) -> Result<Chip, String> {
+        let data = fs::read(path).map_err(|e| format!("Couldn't read '{path}' - {e}"))?;
+        Chip::load_state(&data).map_err(|e| format!("Couldn't load '{path}' - {e}"))
+    }
+}
+
+// --autosave-interval: a save state every so often while running. written to two files
+// in turn (pong.state.0 and pong.state.1 for pong.ch8, or the --save-state path with
+// .0/.1 added) so dying halfway through a write still leaves the other one intact
+pub struct Autosave {
+    paths: [String; 2],
+    next: usize,
+    interval: Duration,
+    last: Instant,
+}
+
+impl Autosave {
+    pub fn new(options: &Options) -> Option<Self> {
+        let interval = Duration::from_secs(options.autosave_interval?);
+        let base = options.save_state.clone().unwrap_or_else(|| match options.rom_path.as_str() {
+            "" => "demo.state".to_string(),
+            rom => Path::new(rom).with_extension("state").to_string_lossy().into_owned(),
+        });
+        Some(Self { paths: [format!("{base}.0"), format!("{base}.1")], next: 0, interval, last: Instant::now() })
+    }
+
+    // cheap enough to call every frame
+    pub fn tick(&mut self, chip: &Chip) {
+        if self.last.elapsed() < self.interval {
+            return;
+        }
+        self.last = Instant::now();
+        let path = &self.paths[self.next];
+        if let Err(e) = fs::write(path, chip.save_state()) {
+            eprintln!("Couldn't autosave to '{path}' - {e}");
+        }
+        self.next = 1 - self.next;
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("save state is truncated".to_string());
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+}
+
+// one line per field that differs between a and b. the framebuffer is drawn with
+// '#' for pixels lit in both, '+' turned on, '-' turned off and '.' off in both
+pub fn diff(a: &Chip, b: &Chip) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut field = |name: &str, before: String, after: String| {
+        if before != after {
+            lines.push(format!("{name}: {before} -> {after}"));
+        }
+    };
+
+    field("ip", format!("0x{:03X}", a.ip), format!("0x{:03X}", b.ip));
+    field("I", format!("0x{:03X}", a.addr_reg), format!("0x{:03X}", b.addr_reg));
+    for reg in 0..16 {
+        field(&format!("V{reg:X}"), format!("0x{:02X}", a.data_regs[reg]), format!("0x{:02X}", b.data_regs[reg]));
+    }
+    field("DT", a.delay_timer.to_string(), b.delay_timer.to_string());
+    field("ST", a.sound_timer.to_string(), b.sound_timer.to_string());
+    field("cycles", a.cycles.to_string(), b.cycles.to_string());
+    field("machine cycles", a.machine_cycles.to_string(), b.machine_cycles.to_string());
+    field("quirks", format!("{:?}", a.quirks), format!("{:?}", b.quirks));
+    let stack = |chip: &Chip| format!("[{}]", chip.stack.iter().map(|addr| format!("0x{addr:03X}")).collect::<Vec<_>>().join(", "));
+    field("stack", stack(a), stack(b));
+
+    for (addr, (before, after)) in a.memory.iter().zip(b.memory.iter()).enumerate() {
+        field(&format!("mem 0x{addr:03X}"), format!("0x{before:02X}"), format!("0x{after:02X}"));
+    }
+
+    let size = |chip: &Chip| format!("{}x{}", chip.screen_width, chip.screen_height);
+    if size(a) != size(b) {
+        field("resolution", size(a), size(b));
+    } else if a.video_memory != b.video_memory {
+        lines.push("display:".to_string());
+        for row in 0..a.screen_height as usize {
+            let start = row * a.screen_width as usize;
+            let end = start + a.screen_width as usize;
+            lines.push(a.video_memory[start..end].iter().zip(&b.video_memory[start..end])
+                .map(|pixels| match pixels {
+                    (1, 1) => '#',
+                    (0, 1) => '+',
+                    (1, 0) => '-',
+                    _ => '.',
+                })
+                .collect());
+        }
+    }
+
+    lines
+}
+
+pub fn diff_files(a: &str, b: &str) -> Result<(), String> {
+    let lines = diff(&Chip::read_state_file(a)?, &Chip::read_state_file(b)?);
+    if lines.is_empty() {
+        println!("no differences");
+    }
+    for line in lines {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{chip_with, run};
+
+    fn busy_chip() -> Chip {
+        let mut chip = chip_with(&[0x60, 0x12, 0x22, 0x04, 0xA3, 0x45, 0xD0, 0x15]);
+        chip.quirks = crate::quirks::preset("schip").unwrap();
+        chip.quirks.vf_per_row = true;
+        run(&mut chip, 4);
+        chip.delay_timer = 7;
+        chip.sound_timer = 3;
+        chip
+    }
+
+    #[test]
+    fn round_trip_keeps_the_machine() {
+        let chip = busy_chip();
+        let loaded = Chip::load_state(&chip.save_state()).unwrap();
+        assert_eq!(loaded.ip, chip.ip);
+        assert_eq!(loaded.addr_reg, 0x345);
+        assert_eq!(loaded.data_regs, chip.data_regs);
+        assert_eq!((loaded.delay_timer, loaded.sound_timer), (7, 3));
+        assert_eq!(loaded.cycles, chip.cycles);
+        assert_eq!(loaded.quirks, chip.quirks);
+        assert_eq!(loaded.stack, vec![0x204]);
+        assert_eq!(loaded.video_memory, chip.video_memory);
+        assert_eq!(loaded.memory[..], chip.memory[..]);
+        assert!(diff(&chip, &loaded).is_empty());
+    }
+
+    #[test]
+    fn version_1_states_still_load() {
+        let chip = busy_chip();
+        let mut data = chip.save_state();
+        // drop the vf per row byte right after the first quirks byte
+        data[4] = 1;
+        data.remove(44);
+        let loaded = Chip::load_state(&data).unwrap();
+        assert!(!loaded.quirks.vf_per_row);
+        assert_eq!(loaded.quirks, Quirks { vf_per_row: false, ..chip.quirks });
+        assert_eq!(loaded.video_memory, chip.video_memory);
+    }
+
+    fn error(data: &[u8]) -> String {
+        Chip::load_state(data).err().unwrap()
+    }
+
+    #[test]
+    fn bad_states_are_refused() {
+        let data = busy_chip().save_state();
+        assert_eq!(error(b"nope"), "not a save state");
+        assert_eq!(error(&data[..100]), "save state is truncated");
+        let mut newer = data.clone();
+        newer[4] = 3;
+        assert_eq!(error(&newer), "unsupported save state version 3");
+        let mut longer = data;
+        longer.push(0);
+        assert_eq!(error(&longer), "trailing data after the save state");
+    }
+
+    // offset of the stack depth byte in a version 2 state
+    const DEPTH: usize = 45;
+
+    #[test]
+    fn stack_deeper_than_the_limit_is_refused() {
+        let mut data = busy_chip().save_state();
+        data[DEPTH] = STACK_LIMIT as u8 + 1;
+        assert_eq!(error(&data), "stack depth 17 is more than 16");
+    }
+
+    #[test]
+    fn bad_resolutions_are_refused() {
+        let data = busy_chip().save_state();
+        // one entry on the stack, then width and height
+        let size = DEPTH + 3;
+        for (width, height) in [(0, 32), (64, 0), (257, 32), (0xFFFF, 0xFFFF)] {
+            let mut bad = data.clone();
+            bad[size..size + 2].copy_from_slice(&u16::to_be_bytes(width));
+            bad[size + 2..size + 4].copy_from_slice(&u16::to_be_bytes(height));
+            assert_eq!(error(&bad), format!("bad resolution {width}x{height}"));
+        }
+    }
+
+    #[test]
+    fn resolution_larger_than_the_data_is_truncated() {
+        let mut data = busy_chip().save_state();
+        let size = DEPTH + 3;
+        data[size..size + 4].copy_from_slice(&[0, 255, 0, 255]);
+        assert_eq!(error(&data), "save state is truncated");
+    }
+}
@@ -0,0 +1,157 @@
+// --serve, a small TCP service for test harnesses. every connection sends one request
+//
+//     <cycles> <rom length>\n
+//     <rom length bytes of ROM>
+//
+// and gets one line of json back: the --json headless summary with the machine state
+// (see Chip::to_json) under "state", or {"error":"..."} if the request didn't make
+// sense. connections are handled one after another, so a client gets REQUEST_TIMEOUT
+// to send its whole request, MAX_HEADER bytes for the first line and can't ask for
+// more than MAX_CYCLES, nobody else is served in the meantime
+
+use crate::{headless, Chip, Options};
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// plenty for two numbers
+const MAX_HEADER: u64 = 64;
+// a couple of seconds of work, over two hours of a ROM at the default 1200 Hz
+const MAX_CYCLES: u64 = 10_000_000;
+
+pub fn serve(template: &Chip, options: &Options, port: u16) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("Couldn't listen on port {port} - {e}"))?;
+    println!("serving on 127.0.0.1:{port}");
+
+    for stream in listener.incoming() {
+        if let Err(e) = stream.and_then(|stream| handle(stream, template, options)) {
+            eprintln!("serve: {e}");
+        }
+    }
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, template: &Chip, options: &Options) -> io::Result<()> {
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(Deadline { stream: stream.try_clone()?, end: Instant::now() + REQUEST_TIMEOUT });
+    let mut header = String::new();
+    reader.by_ref().take(MAX_HEADER).read_line(&mut header)?;
+
+    let max_len = template.max_rom_size;
+    let response = match parse_header(&header) {
+        _ if !header.ends_with('\n') && header.len() as u64 == MAX_HEADER => {
+            format!("{{\"error\":\"the first line can be at most {MAX_HEADER} bytes\"}}")
+        }
+        Some((cycles, _)) if cycles > MAX_CYCLES => format!("{{\"error\":\"at most {MAX_CYCLES} cycles per request\"}}"),
+        Some((cycles, len)) if len <= max_len => {
+            let mut rom = vec![0; len];
+            reader.read_exact(&mut rom)?;
+            headless::run_request(template, options, &rom, cycles)
+        }
+        Some(_) => format!("{{\"error\":\"the ROM can be at most {max_len} bytes\"}}"),
+        None => "{\"error\":\"expected `<cycles> <rom length>` and the ROM\"}".to_string(),
+    };
+    writeln!(stream, "{response}")
+}
+
+// a read timeout on its own is per read, a client sending a byte every few seconds
+// would never run into it. this one is for everything read from the connection
+struct Deadline {
+    stream: TcpStream,
+    end: Instant,
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.end.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "the request took too long"));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+// "1000 246\n" -> (1000, 246)
+fn parse_header(line: &str) -> Option<(u64, usize)> {
+    let (cycles, len) = line.trim().split_once(' ')?;
+    Some((cycles.parse().ok()?, len.trim().parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Shutdown;
+    use std::thread;
+
+    // one request through handle() on a local connection, the reply line
+    fn request(data: &[u8]) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle(stream, &Chip::default(), &Options::default()).unwrap();
+        });
+        client.write_all(data).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        server.join().unwrap();
+        reply
+    }
+
+    #[test]
+    fn parses_the_header() {
+        assert_eq!(parse_header("1000 246\n"), Some((1000, 246)));
+        assert_eq!(parse_header("1000\n"), None);
+        assert_eq!(parse_header("lots 246\n"), None);
+    }
+
+    #[test]
+    fn runs_a_rom() {
+        let reply = request(b"10 4\n\x60\x2a\x12\x02");
+        assert!(reply.starts_with("{\"cycles\":10,"), "{reply}");
+        assert!(reply.contains("\"state\":"), "{reply}");
+    }
+
+    #[test]
+    fn rejects_too_many_cycles() {
+        let reply = request(format!("{} 2\n\x12\x00", u64::MAX).as_bytes());
+        assert_eq!(reply, format!("{{\"error\":\"at most {MAX_CYCLES} cycles per request\"}}\n"));
+    }
+
+    #[test]
+    fn rejects_a_long_first_line() {
+        let reply = request(&[b'1'; 200]);
+        assert_eq!(reply, format!("{{\"error\":\"the first line can be at most {MAX_HEADER} bytes\"}}\n"));
+    }
+
+    #[test]
+    fn the_deadline_covers_the_whole_request() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = Deadline { stream, end: Instant::now() + Duration::from_millis(50) };
+
+        client.write_all(b"10").unwrap();
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).unwrap();
+        // nothing more arrives, the read gives up once the time is over
+        let started = Instant::now();
+        // WouldBlock on unix, TimedOut on windows
+        let kind = reader.read(&mut buf).unwrap_err().kind();
+        assert!(matches!(kind, io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut), "{kind:?}");
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // and data that does arrive afterwards is too late
+        client.write_all(b" 4").unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn rejects_a_bad_header() {
+        assert!(request(b"hello\n").starts_with("{\"error\":"));
+    }
+}
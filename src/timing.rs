@@ -0,0 +1,37 @@
+// rough COSMAC VIP timings for --authentic-timing.
+// the VIP ran its 1802 at 1.76MHz with 8 clocks per machine cycle, so about 3668 machine
+// cycles fit into one 60Hz frame. the costs below are the typical cases of the original
+// interpreter's routines in machine cycles, approximations, not a cycle exact model
+pub const VIP_CYCLES_PER_FRAME: u64 = 3668;
+
+pub fn cycle_cost(instr: u16) -> u32 {
+    let nibbles = [((instr & 0xF000) >> 12) as u8,
+                   ((instr & 0x0F00) >> 8) as u8,
+                   ((instr & 0x00F0) >> 4) as u8,
+                   (instr & 0x000F) as u8];
+
+    match nibbles {
+        [0, 0, 0xE, 0] => 3078,
+        [0, 0, 0xE, 0xE] => 10,
+        [0, ..] => 10,
+        [1, ..] => 12,
+        [2, ..] => 26,
+        [3, ..] | [4, ..] => 10,
+        [5, ..] | [9, ..] => 14,
+        [6, ..] => 6,
+        [7, ..] => 10,
+        [8, ..] => 44,
+        [0xA, ..] => 12,
+        [0xB, ..] => 22,
+        [0xC, ..] => 36,
+        // the real cost depends on the position and what's already on screen,
+        // this is the average for a sprite of n rows
+        [0xD, _, _, n] => 170 + 48 * n as u32,
+        [0xE, ..] => 14,
+        [0xF, _, 0, 7] | [0xF, _, 1, 5] | [0xF, _, 1, 8] => 10,
+        [0xF, _, 1, 0xE] | [0xF, _, 2, 9] => 16,
+        [0xF, _, 3, 3] => 84,
+        [0xF, x, 5, 5] | [0xF, x, 6, 5] => 14 + 14 * (x as u32 + 1),
+        _ => 10,
+    }
+}
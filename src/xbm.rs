@@ -0,0 +1,98 @@
+// X BitMap images, a C source snippet holding a 1-bit image. a natural fit for the
+// monochrome framebuffer: rows are padded to whole bytes and the leftmost pixel of
+// every byte is its lowest bit
+
+pub fn emit(pixels: &[u8], width: u32, height: u32) -> String {
+    let bytes_per_row = width.div_ceil(8) as usize;
+    let mut bytes = vec![0u8; bytes_per_row * height as usize];
+
+    for row in 0..height as usize {
+        for col in 0..width as usize {
+            if pixels[row * width as usize + col] != 0 {
+                bytes[row * bytes_per_row + col / 8] |= 1 << (col % 8);
+            }
+        }
+    }
+
+    let body: Vec<_> = bytes.chunks(12)
+        .map(|line| line.iter().map(|b| format!("0x{b:02x}")).collect::<Vec<_>>().join(", "))
+        .collect();
+
+    format!("#define chip8_width {width}\n#define chip8_height {height}\nstatic unsigned char chip8_bits[] = {{\n   {} }};\n",
+            body.join(",\n   "))
+}
+
+// (width, height, one byte per pixel)
+pub fn parse(text: &str) -> Result<(u32, u32, Vec<u8>), String> {
+    let define = |suffix: &str| {
+        text.lines()
+            .filter_map(|line| line.trim().strip_prefix("#define"))
+            .find_map(|line| {
+                let (name, value) = line.trim().split_once(char::is_whitespace)?;
+                name.ends_with(suffix).then(|| value.trim().parse::<u32>().ok())?
+            })
+            .ok_or(format!("missing {suffix} define"))
+    };
+    let width = define("_width")?;
+    let height = define("_height")?;
+
+    let data = text.split_once('{').ok_or("missing image data")?.1;
+    let data = data.split('}').next().unwrap_or_default();
+    let bytes = data.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+            u8::from_str_radix(hex, 16).map_err(|_| format!("invalid byte '{s}'"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let bytes_per_row = width.div_ceil(8) as usize;
+    if bytes.len() < bytes_per_row * height as usize {
+        return Err(format!("expected {} bytes of image data, found {}", bytes_per_row * height as usize, bytes.len()));
+    }
+
+    let mut pixels = vec![0; (width * height) as usize];
+    for row in 0..height as usize {
+        for col in 0..width as usize {
+            pixels[row * width as usize + col] = (bytes[row * bytes_per_row + col / 8] >> (col % 8)) & 1;
+        }
+    }
+
+    Ok((width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emit_packs_the_leftmost_pixel_into_the_low_bit() {
+        let pixels = [1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1];
+        assert_eq!(emit(&pixels, 12, 1), "#define chip8_width 12\n#define chip8_height 1\nstatic unsigned char chip8_bits[] = {\n   0x01, 0x09 };\n");
+    }
+
+    #[test]
+    fn round_trip() {
+        // a 10x3 diagonal, the rows don't end on a byte boundary
+        let (width, height) = (10, 3);
+        let pixels: Vec<u8> = (0..width * height).map(|i| (i % 11 == 0) as u8).collect();
+        assert_eq!(parse(&emit(&pixels, width, height)), Ok((width, height, pixels)));
+
+        let screen: Vec<u8> = (0..64 * 32).map(|i| (i % 3 == 0) as u8).collect();
+        assert_eq!(parse(&emit(&screen, 64, 32)), Ok((64, 32, screen)));
+    }
+
+    #[test]
+    fn other_spellings_parse() {
+        let text = "#define logo_width 8\n#define logo_height 2\nstatic char logo_bits[] = { 0XFF,0x80 };";
+        assert_eq!(parse(text), Ok((8, 2, vec![1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 1])));
+    }
+
+    #[test]
+    fn broken_images_are_refused() {
+        assert_eq!(parse("#define a_height 1\n{ 0x00 }"), Err("missing _width define".to_string()));
+        assert_eq!(parse("#define a_width 8\n#define a_height 1\n"), Err("missing image data".to_string()));
+        assert_eq!(parse("#define a_width 8\n#define a_height 2\n{ 0x00 }"), Err("expected 2 bytes of image data, found 1".to_string()));
+        assert_eq!(parse("#define a_width 8\n#define a_height 1\n{ 0xZZ }"), Err("invalid byte '0xZZ'".to_string()));
+    }
+}